@@ -0,0 +1,159 @@
+// compares BlockMap's paletted sections with the fixed array it replaced
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+
+// the server is a binary, so borrow just the modules BlockMap needs
+// its tests are compiled under clippy --all-targets but never run here
+#[allow(dead_code, unused_imports)]
+#[path = "../src/rustycraft/block_map.rs"]
+mod block_map;
+#[allow(dead_code)]
+#[path = "../src/rustycraft/block_type.rs"]
+mod block_type;
+mod chunk {
+    pub const CHUNK_SIZE: usize = 16;
+    pub const CHUNK_HEIGHT: usize = 256;
+}
+
+use block_map::BlockMap;
+use block_type::BlockType;
+use chunk::{CHUNK_HEIGHT, CHUNK_SIZE};
+
+// BlockMap as it was before sections, the same x, z, y array but
+// on the heap, as moving it around by value crashes LLVM here
+#[derive(Clone)]
+struct FixedBlockMap {
+    map: Vec<[[BlockType; CHUNK_HEIGHT]; CHUNK_SIZE]>
+}
+
+impl FixedBlockMap {
+    fn new() -> FixedBlockMap {
+        FixedBlockMap { map: vec![[[BlockType::Air; CHUNK_HEIGHT]; CHUNK_SIZE]; CHUNK_SIZE] }
+    }
+
+    fn get(&self, x: usize, y: usize, z: usize) -> BlockType {
+        self.map[x][z][y]
+    }
+
+    fn highest_in_column(&self, x: usize, z: usize) -> usize {
+        for i in 1..CHUNK_HEIGHT {
+            let y = CHUNK_HEIGHT - i;
+            if self.get(x, y, z) != BlockType::Air {
+                return y
+            }
+        }
+        0
+    }
+
+    fn set(&mut self, x: usize, y: usize, z: usize, block: BlockType) {
+        self.map[x][z][y] = block;
+    }
+}
+
+// roughly what terrain generation leaves: stone, dirt, grass
+// and some water up to about y 64, air above
+fn terrain_block(x: usize, y: usize, z: usize) -> BlockType {
+    let surface = 56 + (x * 3 + z * 5) % 8;
+    match y {
+        y if y < surface - 4 => BlockType::Stone,
+        y if y < surface => BlockType::Dirt,
+        y if y == surface => BlockType::Grass,
+        y if y < 60 => BlockType::Water,
+        _ => BlockType::Air
+    }
+}
+
+fn fill(mut set: impl FnMut(usize, usize, usize, BlockType)) {
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            for y in 0..64 {
+                set(x, y, z, terrain_block(x, y, z));
+            }
+        }
+    }
+}
+
+fn filled_block_map() -> BlockMap {
+    let mut map = BlockMap::new();
+    fill(|x, y, z, block| map.set(x, y, z, block));
+    map
+}
+
+fn filled_fixed_block_map() -> FixedBlockMap {
+    let mut map = FixedBlockMap::new();
+    fill(|x, y, z, block| map.set(x, y, z, block));
+    map
+}
+
+fn fill_chunk(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill_chunk");
+    group.bench_function("sections", |b| b.iter(|| black_box(filled_block_map())));
+    group.bench_function("fixed", |b| b.iter(|| black_box(filled_fixed_block_map())));
+    group.finish();
+}
+
+fn get_every_block(c: &mut Criterion) {
+    let sections = filled_block_map();
+    let fixed = filled_fixed_block_map();
+    let mut group = c.benchmark_group("get_every_block");
+    group.bench_function("sections", |b| b.iter(|| {
+        let mut solid = 0;
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_HEIGHT {
+                    solid += (sections.get(x, y, z) != BlockType::Air) as usize;
+                }
+            }
+        }
+        black_box(solid)
+    }));
+    group.bench_function("fixed", |b| b.iter(|| {
+        let mut solid = 0;
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_HEIGHT {
+                    solid += (fixed.get(x, y, z) != BlockType::Air) as usize;
+                }
+            }
+        }
+        black_box(solid)
+    }));
+    group.finish();
+}
+
+fn highest_in_every_column(c: &mut Criterion) {
+    let sections = filled_block_map();
+    let fixed = filled_fixed_block_map();
+    let mut group = c.benchmark_group("highest_in_every_column");
+    group.bench_function("sections", |b| b.iter(|| {
+        (0..CHUNK_SIZE).flat_map(|x| (0..CHUNK_SIZE).map(move |z| (x, z)))
+            .map(|(x, z)| sections.highest_in_column(x, z))
+            .sum::<usize>()
+    }));
+    group.bench_function("fixed", |b| b.iter(|| {
+        (0..CHUNK_SIZE).flat_map(|x| (0..CHUNK_SIZE).map(move |z| (x, z)))
+            .map(|(x, z)| fixed.highest_in_column(x, z))
+            .sum::<usize>()
+    }));
+    group.finish();
+}
+
+fn clone_chunk(c: &mut Criterion) {
+    let sections = filled_block_map();
+    let fixed = filled_fixed_block_map();
+    let mut group = c.benchmark_group("clone_chunk");
+    group.bench_function("sections", |b| b.iter(|| black_box(sections.clone())));
+    group.bench_function("fixed", |b| b.iter(|| black_box(fixed.clone())));
+    group.finish();
+}
+
+fn memory(c: &mut Criterion) {
+    // not timed, just printed alongside for comparison
+    println!(
+        "memory per chunk: sections {} bytes, fixed {} bytes",
+        filled_block_map().memory_usage(), CHUNK_SIZE * CHUNK_SIZE * CHUNK_HEIGHT * std::mem::size_of::<BlockType>()
+    );
+    let _ = c;
+}
+
+criterion_group!(benches, fill_chunk, get_every_block, highest_in_every_column, clone_chunk, memory);
+criterion_main!(benches);
@@ -0,0 +1,75 @@
+use std::{collections::HashMap, net::IpAddr, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}};
+
+// connection counts and banned addresses shared by the accept loop, which
+// checks them before a connection task is started, and the game task,
+// which tracks who joined and keeps the bans up to date
+pub struct Admission {
+    max_players: usize,
+    max_per_ip: usize,
+    online: AtomicUsize,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+    // reason for every banned address, kept in step with the ip ban list
+    banned: Mutex<HashMap<IpAddr, String>>
+}
+
+// held by a connection task for as long as its socket is open
+pub struct Admitted {
+    admission: Arc<Admission>,
+    ip: IpAddr
+}
+
+impl Admission {
+    pub fn new(max_players: usize, max_per_ip: usize) -> Admission {
+        Admission { 
+            max_players, 
+            max_per_ip, 
+            online: AtomicUsize::new(0), 
+            per_ip: Mutex::new(HashMap::new()), 
+            banned: Mutex::new(HashMap::new()) 
+        }
+    }
+
+    // joined players, updated by the game task
+    pub fn set_online(&self, online: usize) {
+        self.online.store(online, Ordering::Relaxed);
+    }
+
+    pub fn set_banned(&self, banned: HashMap<IpAddr, String>) {
+        *self.banned.lock().unwrap_or_else(|e| e.into_inner()) = banned;
+    }
+
+    // returns why the connection is refused, if it is
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<Admitted, String> {
+        if let Some(reason) = self.banned.lock().unwrap_or_else(|e| e.into_inner()).get(&ip) {
+            return Err(format!("Your address is banned: {}", reason))
+        }
+        if self.online.load(Ordering::Relaxed) >= self.max_players {
+            return Err(String::from("Server is full"))
+        }
+        let mut per_ip = self.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        let connections = per_ip.entry(ip).or_insert(0);
+        if *connections >= self.max_per_ip {
+            return Err(String::from("Too many connections from your address"))
+        }
+        *connections += 1;
+        Ok(Admitted { admission: self.clone(), ip })
+    }
+}
+
+impl Admitted {
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+}
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        let mut per_ip = self.admission.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(connections) = per_ip.get_mut(&self.ip) {
+            *connections -= 1;
+            if *connections == 0 {
+                per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::{collections::HashMap, io, path::PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::rustycraft::files::JsonFile;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Ban {
+    pub reason: String,
+    // name of whoever issued the ban
+    pub by: String,
+    // unix time in seconds
+    pub since: u64
+}
+
+// banned player names or addresses, kept in bans.json and ip-bans.json,
+// or muted player names, kept in mutes.json; entries match ignoring case
+pub struct BanList {
+    file: JsonFile<HashMap<String, Ban>>
+}
+
+impl BanList {
+    pub fn load(path: PathBuf) -> io::Result<BanList> {
+        Ok(BanList { file: JsonFile::load(path)? })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Ban> {
+        let key = key.to_lowercase();
+        self.file.value.iter().find(|(banned, _)| banned.to_lowercase() == key).map(|(_, ban)| ban)
+    }
+
+    pub fn bans(&self) -> impl Iterator<Item = (&String, &Ban)> {
+        self.file.value.iter()
+    }
+
+    pub fn add(&mut self, key: &str, ban: Ban) -> io::Result<()> {
+        self.file.value.retain(|banned, _| banned.to_lowercase() != key.to_lowercase());
+        self.file.value.insert(key.to_string(), ban);
+        self.file.save()
+    }
+
+    // returns whether key was banned
+    pub fn remove(&mut self, key: &str) -> io::Result<bool> {
+        let count = self.file.value.len();
+        self.file.value.retain(|banned, _| banned.to_lowercase() != key.to_lowercase());
+        if self.file.value.len() == count {
+            return Ok(false)
+        }
+        self.file.save()?;
+        Ok(true)
+    }
+
+    pub fn file(&mut self) -> &mut JsonFile<HashMap<String, Ban>> {
+        &mut self.file
+    }
+}
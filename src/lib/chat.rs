@@ -0,0 +1,140 @@
+use tokio::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct ChatConfig {
+    // in characters
+    pub max_length: usize,
+    // messages per second a player can keep up
+    pub rate: f32,
+    // messages a player can send at once after being quiet
+    pub burst: f32,
+    // replaced with asterisks wherever they appear as whole words
+    pub filtered_words: Vec<String>
+}
+
+// allowance of chat messages, refilled over time
+pub struct TokenBucket {
+    tokens: f32,
+    refilled: Instant
+}
+
+impl TokenBucket {
+    // starts with as many tokens as the first take allows
+    pub fn full() -> TokenBucket {
+        TokenBucket { tokens: f32::INFINITY, refilled: Instant::now() }
+    }
+
+    // returns false if there was no token left to take
+    pub fn take(&mut self, rate: f32, burst: f32) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f32() * rate;
+        self.tokens = (self.tokens + refill).min(burst);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+// drops ANSI escape sequences and other control characters, so
+// the text cannot restyle the server console or a client's chat
+pub fn sanitize(text: &str) -> String {
+    let mut clean = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{001b}' {
+            // CSI sequences run up to a final byte from @ to ~
+            if chars.peek() == Some(&'[') {
+                chars.next();
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break
+                    }
+                }
+            }
+            continue
+        }
+        if !c.is_control() {
+            clean.push(c);
+        }
+    }
+    clean.trim().to_string()
+}
+
+// stars out filtered words, matching whole words and ignoring case
+pub fn filter_words(text: &str, filtered_words: &[String]) -> String {
+    if filtered_words.is_empty() {
+        return text.to_string()
+    }
+    let mut filtered = String::with_capacity(text.len());
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue
+        }
+        if filtered_words.iter().any(|w| w.to_lowercase() == word.to_lowercase()) {
+            filtered.extend(word.chars().map(|_| '*'));
+        } else {
+            filtered.push_str(&word);
+        }
+        word.clear();
+        filtered.push(c);
+    }
+    // drop the space chained on to flush the last word
+    filtered.pop();
+    filtered
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn sanitize_drops_escapes_and_control_characters() {
+        assert_eq!(sanitize("hello"), "hello");
+        assert_eq!(sanitize("\u{1b}[31mred\u{1b}[0m text"), "red text");
+        assert_eq!(sanitize("bell\u{7}\r\nnew line\ttab"), "bellnew linetab");
+        assert_eq!(sanitize("\u{1b}cstray escape"), "cstray escape");
+        assert_eq!(sanitize("  \u{0}padded  "), "padded");
+        assert_eq!(sanitize("ünïcödé ✓"), "ünïcödé ✓");
+    }
+
+    #[test]
+    fn filter_words_stars_whole_words() {
+        let filtered = vec![String::from("darn"), String::from("Heck")];
+        assert_eq!(filter_words("darn it", &filtered), "**** it");
+        assert_eq!(filter_words("DARN, heck!", &filtered), "****, ****!");
+        // only whole words
+        assert_eq!(filter_words("darnation hecking", &filtered), "darnation hecking");
+        assert_eq!(filter_words("darn", &[]), "darn");
+        assert_eq!(filter_words("", &filtered), "");
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills() {
+        let mut bucket = TokenBucket::full();
+        for _ in 0..3 {
+            assert!(bucket.take(1.0, 3.0));
+        }
+        assert!(!bucket.take(1.0, 3.0));
+
+        // two seconds at one token a second
+        bucket.refilled -= Duration::from_secs(2);
+        assert!(bucket.take(1.0, 3.0));
+        assert!(bucket.take(1.0, 3.0));
+        assert!(!bucket.take(1.0, 3.0));
+
+        // never more than the burst, however long it has been
+        bucket.refilled -= Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.take(1.0, 3.0));
+        }
+        assert!(!bucket.take(1.0, 3.0));
+    }
+}
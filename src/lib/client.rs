@@ -1,75 +1,226 @@
-use std::{io::{BufRead, BufReader, LineWriter, Write}, net::TcpStream, sync::{Arc, Mutex}};
-use uuid::Uuid;
+use std::{collections::HashSet, fmt, net::IpAddr, str::FromStr, sync::Arc, time::Duration};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::{Notify, mpsc::{self, error::{SendTimeoutError, TrySendError}}}, time::Instant};
 
-use super::events::RustyCraftMessage;
+use super::{chat::TokenBucket, event::{EncodedEvent, serialize_event}, events::RustyCraftMessage, handshake::LEGACY_PROTOCOL_VERSION, player_id::PlayerId, streaming::ChunkStream};
 
-// struct to represent player-server connection read/writing 
-// and server player data
-pub struct Client {
-    pub id: String,
-    pub name: Arc<Mutex<Option<String>>>,
-    stream: TcpStream,
-    reader: BufReader<TcpStream>,
-    writer: LineWriter<TcpStream>,
-    pub x: Arc<Mutex<f32>>,
-    pub y: Arc<Mutex<f32>>,
-    pub z: Arc<Mutex<f32>>,
-    pub pitch: Arc<Mutex<f32>>,
-    pub yaw: Arc<Mutex<f32>> 
+// default cap on a single incoming line, large enough
+// for a GetChunks request spanning a generous view distance
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+// number of events that may wait on a
+// client's connection task before the overflow policy applies
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// longest the block policy waits for room before disconnecting; the
+// game task is stalled while it waits, so this is kept short
+pub const MAX_BLOCK_WAIT: Duration = Duration::from_millis(250);
+
+// what to do when a client's outbound queue is full
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum OverflowPolicy {
+    // drop movement updates, which the next one supersedes anyway,
+    // and disconnect if anything else does not fit
+    #[serde(rename = "drop")]
+    DropPositionUpdates,
+    #[serde(rename = "disconnect")]
+    Disconnect,
+    // wait up to MAX_BLOCK_WAIT for the connection task to make room,
+    // then disconnect
+    #[serde(rename = "block")]
+    Block
 }
 
-impl Clone for Client {
-    fn clone(&self) -> Self {
-        let reader = BufReader::new(self.stream.try_clone().unwrap());
-        let writer = LineWriter::new(self.stream.try_clone().unwrap());
-        Client { 
-            id: self.id.clone(), 
-            stream: self.stream.try_clone().unwrap(), 
-            reader, 
-            writer, 
-            name: self.name.clone(), 
-            x: self.x.clone(), 
-            y: self.y.clone(), 
-            z: self.z.clone(), 
-            pitch: self.pitch.clone(), 
-            yaw: self.yaw.clone() 
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(OverflowPolicy::DropPositionUpdates),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            "block" => Ok(OverflowPolicy::Block),
+            _ => Err(format!("unknown overflow policy '{}', expected drop, disconnect or block", s))
         }
     }
 }
 
-impl Client {
-    pub fn new(stream: TcpStream) -> Client {
-        let reader = BufReader::new(stream.try_clone().unwrap());
-        let writer = LineWriter::new(stream.try_clone().unwrap());
-
-        // name is not set until SetName packet is received
-        let name = Arc::new(Mutex::new(None));
-        let x = Arc::new(Mutex::new(0.0));
-        let y = Arc::new(Mutex::new(0.0));
-        let z = Arc::new(Mutex::new(0.0));
-        let pitch = Arc::new(Mutex::new(0.0));
-        let yaw = Arc::new(Mutex::new(-90.0));
-        Client { id: Uuid::new_v4().to_string(), name, stream, reader, writer, x, y, z, pitch, yaw }
+#[derive(Debug, Copy, Clone)]
+pub struct ClientConfig {
+    pub max_line_length: usize,
+    pub queue_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub write_timeout: Duration
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::DropPositionUpdates,
+            write_timeout: DEFAULT_WRITE_TIMEOUT
+        }
     }
-    
-    pub fn set_name(&mut self, name: String) {
-        *self.name.lock().unwrap() = Some(name);
+}
+
+// reasons an event could not be queued for a client
+#[derive(Debug, PartialEq)]
+pub enum SendError {
+    // connection task has stopped, the socket is gone
+    Closed,
+    // queue was full and the overflow policy disconnected the client
+    Overflow
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Closed => write!(f, "connection closed"),
+            SendError::Overflow => write!(f, "outbound queue overflowed")
+        }
     }
+}
+
+pub enum Outgoing {
+    Event(Arc<EncodedEvent>),
+    // flush what is queued and shut the socket down
+    Close
+}
+
+// handle onto a client's outbound queue, drained by its connection task
+pub struct Outbound {
+    sender: mpsc::Sender<Outgoing>,
+    // closes the connection without waiting for the queue to drain
+    closer: Arc<Notify>,
+    policy: OverflowPolicy
+}
 
-    pub fn send(&mut self, message: &String) {
-        self.writer.write(&message.as_bytes()).unwrap();
-        self.writer.write(&[b'\n']).unwrap();
+impl Outbound {
+    pub fn new(sender: mpsc::Sender<Outgoing>, closer: Arc<Notify>, policy: OverflowPolicy) -> Outbound {
+        Outbound { sender, closer, policy }
     }
 
-    pub fn read(&mut self) -> Option<RustyCraftMessage> {
-        let mut buffer = String::new();
-        let bytes_read = self.reader.read_line(&mut buffer).unwrap();
-        match bytes_read {
-            0 => None,
-            _ => {
-                buffer.pop();
-                Some(serde_json::from_str(buffer.as_str()).unwrap())
+    pub async fn send(&self, event: Arc<EncodedEvent>) -> Result<(), SendError> {
+        let droppable = event.message().is_position_update();
+        let event = Outgoing::Event(event);
+        if self.policy == OverflowPolicy::Block {
+            return match self.sender.send_timeout(event, MAX_BLOCK_WAIT).await {
+                Ok(()) => Ok(()),
+                Err(SendTimeoutError::Closed(_)) => Err(SendError::Closed),
+                Err(SendTimeoutError::Timeout(_)) => {
+                    self.closer.notify_one();
+                    Err(SendError::Overflow)
+                }
+            }
+        }
+
+        match self.sender.try_send(event) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed(_)) => Err(SendError::Closed),
+            Err(TrySendError::Full(_)) => {
+                if droppable && self.policy == OverflowPolicy::DropPositionUpdates {
+                    return Ok(())
+                }
+                self.closer.notify_one();
+                Err(SendError::Overflow)
             }
         }
     }
-}
\ No newline at end of file
+
+    pub fn close(&self) {
+        if self.sender.try_send(Outgoing::Close).is_err() {
+            self.closer.notify_one();
+        }
+    }
+}
+
+// server-side player data for a connection,
+// owned by the game task
+pub struct Client {
+    pub id: PlayerId,
+    pub name: Option<String>,
+    pub address: IpAddr,
+    outbound: Outbound,
+    // from Hello, messages newer than this are never seen by the client
+    pub protocol_version: u32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub connected_at: Instant,
+    // when the last position update was accepted
+    pub last_move: Instant,
+    // rejected position updates so far
+    pub movement_violations: u32,
+    pub chat_tokens: TokenBucket,
+    pub chunk_tokens: TokenBucket,
+    // channels joined, in lowercase
+    pub channels: HashSet<String>,
+    // whoever /r answers, the last player whispered to or from
+    pub reply_to: Option<PlayerId>,
+    // staff receiving copies of every whisper
+    pub spying: bool,
+    // players this client has been sent SpawnPlayer for, who also see it
+    pub visible: HashSet<PlayerId>,
+    pub chunks: ChunkStream
+}
+
+impl Client {
+    pub fn new(id: PlayerId, address: IpAddr, outbound: Outbound) -> Client {
+        // name is not set until PlayerJoin packet is received
+        Client { 
+            id, 
+            name: None, 
+            address, 
+            outbound, 
+            protocol_version: LEGACY_PROTOCOL_VERSION, 
+            x: 0.0, 
+            y: 0.0, 
+            z: 0.0, 
+            pitch: 0.0, 
+            yaw: -90.0, 
+            connected_at: Instant::now(), 
+            last_move: Instant::now(), 
+            movement_violations: 0, 
+            chat_tokens: TokenBucket::full(), 
+            chunk_tokens: TokenBucket::full(), 
+            channels: HashSet::new(), 
+            reply_to: None, 
+            spying: false, 
+            visible: HashSet::new(), 
+            chunks: ChunkStream::new() 
+        }
+    }
+
+    // whether the client is sent this message at all, as opposed to
+    // an older equivalent or nothing
+    pub fn understands(&self, message: &RustyCraftMessage) -> bool {
+        message.since_version() <= self.protocol_version
+    }
+
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => String::from("[Unnamed Player]")
+        }
+    }
+
+    pub async fn send(&self, event: Arc<EncodedEvent>) -> Result<(), SendError> {
+        self.outbound.send(event).await
+    }
+
+    // tell the client why it is being dropped, then close the socket
+    // once everything queued before it has been written
+    pub fn kick(&self, reason: &str) {
+        let message = serialize_event(PlayerId::SERVER, RustyCraftMessage::Kick { reason: reason.to_string() });
+        let _ = self.outbound.sender.try_send(Outgoing::Event(message));
+        self.close();
+    }
+
+    pub fn close(&self) {
+        self.outbound.close();
+    }
+}
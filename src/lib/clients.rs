@@ -1,56 +1,61 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}};
+use std::collections::HashMap;
 
-use super::{client::Client, event::serialize_event, events::RustyCraftMessage};
+use super::{client::Client, event::serialize_event, events::RustyCraftMessage, player_id::PlayerId};
 
+// connected clients, owned by the game task
 pub struct Clients {
-    // thread-safe hashmap of UUIDs to Clients
-    clients: Arc<Mutex<HashMap<String, Arc<Mutex<Client>>>>>
-}
-
-impl Clone for Clients {
-    fn clone(&self) -> Self {
-        Clients { clients: self.clients.clone() }
-    }
+    clients: HashMap<PlayerId, Client>
 }
 
 impl Clients {
     pub fn new() -> Clients {
-        Clients { clients: Arc::new(Mutex::new(HashMap::new())) }
+        Clients { clients: HashMap::new() }
     }
 
-    pub fn clients(&self) -> Vec<Client> {
-        self.clients.lock().unwrap().values().fold(Vec::new(), |mut client_vec, client| {
-            client_vec.push(client.lock().unwrap().clone());
-            client_vec
-        })
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
     }
 
-    pub fn add(&self, client: Client) {
-        self.clients.lock().unwrap().insert(client.id.clone(), Arc::new(Mutex::new(client)));
+    pub fn clients_mut(&mut self) -> impl Iterator<Item = &mut Client> {
+        self.clients.values_mut()
     }
 
-    pub fn remove(&self, id: &String) {
-        self.clients.lock().unwrap().remove(id)
-            .expect("Failed to remove client");
+    pub fn get(&self, id: PlayerId) -> Option<&Client> {
+        self.clients.get(&id)
     }
-    
-    pub fn broadcast(&self, message: &RustyCraftMessage, sender_id: &String) {
-        let message = message.clone();
-        let event = serialize_event(sender_id.clone(), message);
-        for (_, client) in self.clients.lock().unwrap().iter() {
-            client.lock().unwrap().send(&event);
-        }
+
+    pub fn get_mut(&mut self, id: PlayerId) -> Option<&mut Client> {
+        self.clients.get_mut(&id)
+    }
+
+    // joined client with the given name, ignoring case as
+    // no two players online can differ only by case
+    pub fn find(&self, name: &str) -> Option<&Client> {
+        self.clients.values().find(|c| c.name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(name)))
+    }
+
+    pub fn add(&mut self, client: Client) {
+        self.clients.insert(client.id, client);
     }
 
-    // broadcast to all clients except one 
-    pub fn broadcast_to_peers(&self, message: &RustyCraftMessage, sender_id: &String) {
-        let message = message.clone();
-        let event = serialize_event(sender_id.clone(), message);
-        for (id, client) in self.clients.lock().unwrap().iter() {
-            if id == sender_id {
+    pub fn remove(&mut self, id: PlayerId) -> Option<Client> {
+        self.clients.remove(&id)
+    }
+    
+    // only reaches clients that have joined; returns the
+    // ids of clients that could not be sent to
+    pub async fn broadcast(&self, message: &RustyCraftMessage, sender_id: PlayerId) -> Vec<PlayerId> {
+        // encoded lazily per wire format and shared between clients
+        let event = serialize_event(sender_id, message.clone());
+        let mut failed = Vec::new();
+        for (id, client) in self.clients.iter() {
+            if client.name.is_none() {
                 continue;
             }
-            client.lock().unwrap().send(&event);
+            if client.send(event.clone()).await.is_err() {
+                failed.push(*id);
+            }
         }
+        failed
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,298 @@
+use std::{fmt, io, sync::Arc};
+use bincode::{DefaultOptions, Options};
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{chat::sanitize, event::EncodedEvent, events::RustyCraftMessage};
+
+// wire formats a connection can speak
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Framing {
+    // one JSON message per line, the default
+    Json,
+    // u32 little-endian length prefix followed by a bincode payload
+    Binary
+}
+
+// reasons a client's incoming line could not be turned into a message
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    InvalidUtf8,
+    UnknownMessage(String),
+    LineTooLong(usize)
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "connection error: {}", e),
+            ReadError::InvalidUtf8 => write!(f, "message was not valid UTF-8"),
+            ReadError::UnknownMessage(e) => write!(f, "unrecognized message: {}", e),
+            ReadError::LineTooLong(max) => write!(f, "message exceeded the {} byte limit", max)
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+// most characters of a parse error kept, since it can quote the input
+const MAX_ERROR_LENGTH: usize = 128;
+
+// parse errors quote what the client sent, such as an unknown variant
+// name, and end up in the console and the Kick sent back
+fn unknown_message(e: impl fmt::Display) -> ReadError {
+    ReadError::UnknownMessage(sanitize(&e.to_string()).chars().take(MAX_ERROR_LENGTH).collect())
+}
+
+// client messages in, encoded events out, in either framing
+pub struct MessageCodec {
+    framing: Framing,
+    max_line_length: usize,
+    // bytes already scanned for a newline, so a partial
+    // line is not searched again on every read
+    scanned: usize
+}
+
+impl MessageCodec {
+    pub fn new(max_line_length: usize) -> MessageCodec {
+        MessageCodec { framing: Framing::Json, max_line_length, scanned: 0 }
+    }
+
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+        self.scanned = 0;
+    }
+
+    fn decode_line(&mut self, src: &mut BytesMut) -> Result<Option<RustyCraftMessage>, ReadError> {
+        let newline = src[self.scanned..].iter().position(|b| *b == b'\n');
+        let end = match newline {
+            Some(offset) => self.scanned + offset,
+            None => {
+                if src.len() > self.max_line_length {
+                    return Err(ReadError::LineTooLong(self.max_line_length))
+                }
+                self.scanned = src.len();
+                return Ok(None)
+            }
+        };
+        self.scanned = 0;
+        if end > self.max_line_length {
+            return Err(ReadError::LineTooLong(self.max_line_length))
+        }
+
+        let line = src.split_to(end + 1);
+        let mut line = &line[..end];
+        if line.last() == Some(&b'\r') {
+            line = &line[..line.len() - 1];
+        }
+
+        let line = std::str::from_utf8(line).map_err(|_| ReadError::InvalidUtf8)?;
+        serde_json::from_str(line)
+            .map(Some)
+            .map_err(unknown_message)
+    }
+
+    fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<RustyCraftMessage>, ReadError> {
+        if src.len() < LENGTH_PREFIX_SIZE {
+            return Ok(None)
+        }
+
+        let length = u32::from_le_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if length > self.max_line_length {
+            return Err(ReadError::LineTooLong(self.max_line_length))
+        }
+        if src.len() < LENGTH_PREFIX_SIZE + length {
+            src.reserve(LENGTH_PREFIX_SIZE + length - src.len());
+            return Ok(None)
+        }
+
+        src.advance(LENGTH_PREFIX_SIZE);
+        let payload = src.split_to(length);
+        DefaultOptions::new()
+            .with_limit(self.max_line_length as u64)
+            .deserialize(&payload)
+            .map(Some)
+            .map_err(unknown_message)
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = RustyCraftMessage;
+    type Error = ReadError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.framing {
+            Framing::Json => self.decode_line(src),
+            Framing::Binary => self.decode_frame(src)
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(message) = self.decode(src)? {
+            return Ok(Some(message))
+        }
+        if src.is_empty() || self.framing == Framing::Binary {
+            return Ok(None)
+        }
+        // treat a trailing unterminated line as a final message
+        src.put_u8(b'\n');
+        self.decode(src)
+    }
+}
+
+impl Encoder<Arc<EncodedEvent>> for MessageCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, event: Arc<EncodedEvent>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match self.framing {
+            Framing::Json => {
+                let line = event.json();
+                dst.reserve(line.len() + 1);
+                dst.put_slice(line);
+                dst.put_u8(b'\n');
+            },
+            Framing::Binary => {
+                let payload = event.binary();
+                dst.reserve(LENGTH_PREFIX_SIZE + payload.len());
+                dst.put_u32_le(payload.len() as u32);
+                dst.put_slice(payload);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    const MAX: usize = 1024;
+
+    fn json_codec() -> MessageCodec {
+        MessageCodec::new(MAX)
+    }
+
+    fn binary_codec() -> MessageCodec {
+        let mut codec = MessageCodec::new(MAX);
+        codec.set_framing(Framing::Binary);
+        codec
+    }
+
+    // decodes until the codec wants more bytes or gives up on the connection
+    fn drain(codec: &mut MessageCodec, src: &mut BytesMut) -> Result<Vec<RustyCraftMessage>, ReadError> {
+        let mut messages = Vec::new();
+        while let Some(message) = codec.decode(src)? {
+            messages.push(message);
+        }
+        if let Some(message) = codec.decode_eof(src)? {
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..2000 {
+            let length = rng.gen_range(0..2 * MAX);
+            let bytes: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            for mut codec in [json_codec(), binary_codec()] {
+                let _ = drain(&mut codec, &mut BytesMut::from(&bytes[..]));
+            }
+        }
+    }
+
+    #[test]
+    fn mangled_messages_never_panic() {
+        let valid = br#"{"SetBlock":{"block":"Stone","world_x":1,"world_y":2,"world_z":3}}"#;
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..2000 {
+            let mut bytes = valid.to_vec();
+            for _ in 0..rng.gen_range(1..8) {
+                let at = rng.gen_range(0..bytes.len());
+                match rng.gen_range(0..3) {
+                    0 => bytes[at] = rng.gen(),
+                    1 => { bytes.remove(at); },
+                    _ => bytes.insert(at, rng.gen())
+                }
+            }
+            bytes.push(b'\n');
+            let _ = drain(&mut json_codec(), &mut BytesMut::from(&bytes[..]));
+        }
+    }
+
+    #[test]
+    fn line_split_across_reads() {
+        let mut codec = json_codec();
+        let mut src = BytesMut::from(&br#"{"ChatMessage":{"con"#[..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(b"tent\":\"hi\"}}\r\n\"Disconnect\"\n");
+        let messages = drain(&mut codec, &mut src).unwrap();
+        assert!(matches!(&messages[0], RustyCraftMessage::ChatMessage { content } if content == "hi"));
+        assert!(matches!(messages[1], RustyCraftMessage::Disconnect));
+    }
+
+    #[test]
+    fn unterminated_last_line_is_decoded_at_eof() {
+        let messages = drain(&mut json_codec(), &mut BytesMut::from(&b"\"Disconnect\""[..])).unwrap();
+        assert!(matches!(messages[..], [RustyCraftMessage::Disconnect]));
+    }
+
+    #[test]
+    fn invalid_utf8_is_refused() {
+        let result = json_codec().decode(&mut BytesMut::from(&b"\"Disc\xffonnect\"\n"[..]));
+        assert!(matches!(result, Err(ReadError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn long_lines_are_refused() {
+        let long = vec![b'a'; MAX + 1];
+        let result = json_codec().decode(&mut BytesMut::from(&long[..]));
+        assert!(matches!(result, Err(ReadError::LineTooLong(MAX))));
+
+        let mut terminated = long;
+        terminated.push(b'\n');
+        let result = json_codec().decode(&mut BytesMut::from(&terminated[..]));
+        assert!(matches!(result, Err(ReadError::LineTooLong(MAX))));
+    }
+
+    #[test]
+    fn large_frames_are_refused_before_buffering() {
+        let mut src = BytesMut::new();
+        src.put_u32_le(u32::MAX);
+        let result = binary_codec().decode(&mut src);
+        assert!(matches!(result, Err(ReadError::LineTooLong(MAX))));
+    }
+
+    #[test]
+    fn partial_frame_waits_for_more() {
+        let mut src = BytesMut::new();
+        src.put_u32_le(10);
+        src.put_slice(&[0; 4]);
+        assert!(binary_codec().decode(&mut src).unwrap().is_none());
+    }
+
+    #[test]
+    fn errors_do_not_echo_escapes() {
+        // the unknown variant name comes back quoted in serde's error
+        let line = format!("\"\\u001b[2J\\u001b[31mOwned{}\"\n", "x".repeat(500));
+        let error = match json_codec().decode(&mut BytesMut::from(line.as_bytes())) {
+            Err(ReadError::UnknownMessage(e)) => e,
+            other => panic!("expected UnknownMessage, got {:?}", other)
+        };
+        assert!(error.contains("Owned"));
+        assert!(!error.chars().any(|c| c.is_control()));
+        assert!(error.chars().count() <= MAX_ERROR_LENGTH);
+    }
+}
@@ -0,0 +1,523 @@
+use std::{net::IpAddr, time::{Duration, SystemTime, UNIX_EPOCH}};
+use tokio::{task::block_in_place, time::Instant};
+
+use crate::rustycraft::{chunk::CHUNK_HEIGHT, spawn::SpawnPoint};
+
+use super::{bans::Ban, chat::filter_words, console, event::serialize_event, history::clock_time, events::RustyCraftMessage, ops::{LEVEL_ADMIN, LEVEL_MODERATOR, LEVEL_OWNER, LEVEL_PLAYER}, player_id::PlayerId, state::State};
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    // lowest permission level allowed to run it
+    level: u8
+}
+
+const COMMANDS: &[Command] = &[
+    Command { name: "help", usage: "/help", description: "list the commands you can use", level: LEVEL_PLAYER },
+    Command { name: "list", usage: "/list", description: "list online players", level: LEVEL_PLAYER },
+    Command { name: "time", usage: "/time", description: "show the server time and uptime", level: LEVEL_PLAYER },
+    Command { name: "msg", usage: "/msg <player> <message>", description: "whisper to a player", level: LEVEL_PLAYER },
+    Command { name: "r", usage: "/r <message>", description: "reply to the last whisper", level: LEVEL_PLAYER },
+    Command { name: "history", usage: "/history [page]", description: "show earlier chat, page 1 being the latest", level: LEVEL_PLAYER },
+    Command { name: "channel", usage: "/channel <join <name> | leave <name> | list>", description: "join or leave chat channels", level: LEVEL_PLAYER },
+    Command { name: "ch", usage: "/ch <channel> <message>", description: "chat in a channel you have joined", level: LEVEL_PLAYER },
+    Command { name: "spy", usage: "/spy", description: "toggle receiving copies of every whisper", level: LEVEL_MODERATOR },
+    Command { name: "say", usage: "/say <message>", description: "announce a message to everyone", level: LEVEL_MODERATOR },
+    Command { name: "tp", usage: "/tp [player] <target player | x y z>", description: "teleport a player", level: LEVEL_MODERATOR },
+    Command { name: "mute", usage: "/mute <player> [reason]", description: "stop a player from chatting", level: LEVEL_MODERATOR },
+    Command { name: "unmute", usage: "/unmute <player>", description: "let a muted player chat again", level: LEVEL_MODERATOR },
+    Command { name: "kick", usage: "/kick <player> [reason]", description: "disconnect a player", level: LEVEL_MODERATOR },
+    Command { name: "ban", usage: "/ban <player> [reason]", description: "keep a player off the server", level: LEVEL_ADMIN },
+    Command { name: "unban", usage: "/unban <player>", description: "lift a ban", level: LEVEL_ADMIN },
+    Command { name: "ban-ip", usage: "/ban-ip <address | player> [reason]", description: "keep everyone from an address off the server", level: LEVEL_ADMIN },
+    Command { name: "unban-ip", usage: "/unban-ip <address>", description: "lift an address ban", level: LEVEL_ADMIN },
+    Command { name: "whitelist", usage: "/whitelist <on | off | list | add <player> | remove <player>>", description: "only let listed players join", level: LEVEL_ADMIN },
+    Command { name: "reload", usage: "/reload", description: "read the ops, bans, whitelist and mutes files again", level: LEVEL_ADMIN },
+    Command { name: "seed", usage: "/seed", description: "show the world seed", level: LEVEL_ADMIN },
+    Command { name: "setspawn", usage: "/setspawn [radius]", description: "move the spawn point to where you stand", level: LEVEL_ADMIN },
+    Command { name: "chunks", usage: "/chunks", description: "show how many chunks are loaded and their memory use", level: LEVEL_ADMIN },
+    Command { name: "save-all", usage: "/save-all", description: "save the world now", level: LEVEL_ADMIN },
+    Command { name: "op", usage: "/op <player> [level]", description: "make a player an operator", level: LEVEL_OWNER },
+    Command { name: "deop", usage: "/deop <player>", description: "take a player's operator status", level: LEVEL_OWNER },
+    Command { name: "stop", usage: "/stop", description: "save and shut the server down", level: LEVEL_OWNER }
+];
+
+// only staff can join it, and they can use it to talk among themselves
+const STAFF_CHANNEL: &str = "staff";
+
+const MAX_CHANNEL_NAME_LENGTH: usize = 16;
+
+// chat lines per /history page
+const HISTORY_PAGE_SIZE: usize = 10;
+
+// commands that log what was said themselves
+const CHAT_COMMANDS: &[&str] = &["msg", "r", "ch"];
+
+// who a command is run for; the console can use every command
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Issuer {
+    Player(PlayerId),
+    Console
+}
+
+// reply to show the issuer, or why the command failed
+type CommandResult = Result<Option<String>, String>;
+
+// runs a command, the leading / being optional; output goes only to the
+// issuer, as system messages for players and printed for the console
+pub async fn run_command(state: &mut State, issuer: Issuer, line: &str) {
+    let (issuer_name, level) = match issuer {
+        Issuer::Player(id) => match state.clients.get(id) {
+            Some(client) => (client.display_name(), state.permission_level(id)),
+            None => return
+        },
+        Issuer::Console => (String::from("Server"), LEVEL_OWNER)
+    };
+    let mut words = line.trim_start_matches('/').split_whitespace();
+    let name = words.next().unwrap_or("");
+    let args: Vec<&str> = words.collect();
+
+    let result = match COMMANDS.iter().find(|c| c.name == name) {
+        None => Err(format!("Unknown command /{}, try /help", name)),
+        Some(command) if level < command.level => Err(format!("You do not have permission to use /{}", name)),
+        Some(command) => {
+            if issuer != Issuer::Console && !CHAT_COMMANDS.contains(&command.name) {
+                log!(Info, "\u{001b}[37m{} issued {}\u{001b}[0m", issuer_name, line.trim());
+            }
+            execute(state, issuer, &issuer_name, level, command, &args).await
+        }
+    };
+
+    let content = match result {
+        Ok(Some(reply)) | Err(reply) => reply,
+        Ok(None) => return
+    };
+    match issuer {
+        Issuer::Player(id) => {
+            let reply = RustyCraftMessage::SystemMessage { content };
+            state.send_or_drop(id, serialize_event(PlayerId::SERVER, reply)).await;
+        },
+        Issuer::Console => console::print(format!("\u{001b}[37;1m{}\u{001b}[0m", content))
+    }
+}
+
+async fn execute(state: &mut State, issuer: Issuer, issuer_name: &str, level: u8, command: &Command, args: &[&str]) -> CommandResult {
+    let usage = || format!("Usage: {}", command.usage);
+    match (command.name, args) {
+        ("help", []) => {
+            let lines: Vec<String> = COMMANDS.iter()
+                .filter(|c| c.level <= level)
+                .map(|c| format!("{} - {}", c.usage, c.description))
+                .collect();
+            Ok(Some(lines.join("\n")))
+        },
+        ("list", []) => {
+            let names: Vec<String> = state.clients.clients().filter_map(|c| c.name.clone()).collect();
+            Ok(Some(format!("{} online: {}", names.len(), names.join(", "))))
+        },
+        ("time", []) => {
+            Ok(Some(format!(
+                "Server time is {} UTC, up for {}",
+                clock_time(unix_time()), format_duration(state.started.elapsed())
+            )))
+        },
+        ("msg", [name, words @ ..]) if !words.is_empty() => {
+            let to = find_player(state, name)?;
+            whisper(state, issuer, issuer_name, to, &words.join(" ")).await
+        },
+        ("r", words) if !words.is_empty() => {
+            let to = match issuer {
+                Issuer::Player(id) => state.clients.get(id).unwrap().reply_to,
+                Issuer::Console => None
+            };
+            let to = to.ok_or_else(|| String::from("No one has whispered to you yet"))?;
+            if state.clients.get(to).is_none() {
+                return Err(String::from("They are no longer online"))
+            }
+            whisper(state, issuer, issuer_name, to, &words.join(" ")).await
+        },
+        ("history", args) if args.len() <= 1 => {
+            let page = match args.first() {
+                Some(page) => page.parse::<usize>().ok()
+                    .filter(|page| *page > 0)
+                    .ok_or_else(|| format!("Invalid page {}", page))?,
+                None => 1
+            };
+            let pages = state.history.pages(HISTORY_PAGE_SIZE);
+            if page > pages {
+                return Err(match pages {
+                    0 => String::from("No chat to show yet"),
+                    _ => format!("There are only {} pages of chat", pages)
+                })
+            }
+            let lines = state.history.page(page, HISTORY_PAGE_SIZE);
+            let heading = format!("Chat history, page {} of {}", page, pages);
+            match issuer {
+                Issuer::Player(id) => {
+                    state.send_system_message(id, heading).await;
+                    let history = RustyCraftMessage::ChatHistory { lines };
+                    state.send_or_drop(id, serialize_event(PlayerId::SERVER, history)).await;
+                    Ok(None)
+                },
+                Issuer::Console => Ok(Some(format!("{}\n{}", heading, lines.join("\n"))))
+            }
+        },
+        ("channel", ["join", channel]) => {
+            let id = player_only(issuer)?;
+            let channel = channel_name(channel)?;
+            if channel == STAFF_CHANNEL && level < LEVEL_MODERATOR {
+                return Err(String::from("Only staff can join the staff channel"))
+            }
+            let client = state.clients.get_mut(id).unwrap();
+            if !client.channels.insert(channel.clone()) {
+                return Err(format!("You are already in #{}", channel))
+            }
+            Ok(Some(format!("Joined #{}, talk there with /ch {} <message>", channel, channel)))
+        },
+        ("channel", ["leave", channel]) => {
+            let id = player_only(issuer)?;
+            let channel = channel.to_lowercase();
+            match state.clients.get_mut(id).unwrap().channels.remove(&channel) {
+                true => Ok(Some(format!("Left #{}", channel))),
+                false => Err(format!("You are not in #{}", channel))
+            }
+        },
+        ("channel", ["list"]) => {
+            let id = player_only(issuer)?;
+            let mut channels: Vec<String> = state.clients.get(id).unwrap().channels.iter()
+                .map(|channel| format!("#{} ({} online)", channel, channel_members(state, channel).len()))
+                .collect();
+            channels.sort();
+            match channels.is_empty() {
+                true => Ok(Some(String::from("You are not in any channels"))),
+                false => Ok(Some(format!("Your channels: {}", channels.join(", "))))
+            }
+        },
+        ("ch", [channel, words @ ..]) if !words.is_empty() => {
+            let id = player_only(issuer)?;
+            let channel = channel.to_lowercase();
+            if !state.clients.get(id).unwrap().channels.contains(&channel) {
+                return Err(format!("You are not in #{}, join it with /channel join {}", channel, channel))
+            }
+            check_not_muted(state, issuer, issuer_name)?;
+            let content = filter_words(&words.join(" "), &state.chat.filtered_words);
+            log!(Info, "\u{001b}[33m[#{}] <{}> {}\u{001b}[0m", channel, issuer_name, content);
+            state.history.record(&format!("[#{}] <{}> {}", channel, issuer_name, content), false);
+            let members = channel_members(state, &channel);
+            state.send_to(&members, RustyCraftMessage::ChannelMessage { from: issuer_name.to_string(), channel, content }, id).await;
+            Ok(None)
+        },
+        ("spy", []) => {
+            let id = player_only(issuer)?;
+            let client = state.clients.get_mut(id).unwrap();
+            client.spying = !client.spying;
+            match client.spying {
+                true => Ok(Some(String::from("You will now see every whisper"))),
+                false => Ok(Some(String::from("You will no longer see other players' whispers")))
+            }
+        },
+        ("say", words) if !words.is_empty() => {
+            let content = format!("[{}] {}", issuer_name, words.join(" "));
+            log!(Info, "\u{001b}[35m{}\u{001b}[0m", content);
+            state.history.record(&content, true);
+            state.broadcast(&RustyCraftMessage::SystemMessage { content }, PlayerId::SERVER).await;
+            Ok(None)
+        },
+        ("tp", args) if !args.is_empty() && args.len() <= 4 => {
+            // an odd number of arguments means the first one names who to move
+            let (player, destination) = match args.len() % 2 {
+                1 => match issuer {
+                    Issuer::Player(id) => (id, args),
+                    Issuer::Console => return Err(String::from("Name the player to teleport"))
+                },
+                _ => (find_player(state, args[0])?, &args[1..])
+            };
+            let position = match destination {
+                [target] => {
+                    let target = state.clients.get(find_player(state, target)?).unwrap();
+                    (target.x, target.y, target.z)
+                },
+                [x, y, z] => parse_position(x, y, z)?,
+                _ => return Err(usage())
+            };
+            teleport(state, player, position).await?;
+            let name = state.clients.get(player).map(|c| c.display_name()).unwrap_or_default();
+            Ok(Some(format!("Teleported {} to {:.1}, {:.1}, {:.1}", name, position.0, position.1, position.2)))
+        },
+        ("mute", [name, reason @ ..]) => {
+            let reason = if reason.is_empty() { String::from("Muted by an operator") } else { reason.join(" ") };
+            let mute = Ban { reason: reason.clone(), by: issuer_name.to_string(), since: unix_time() };
+            state.mutes.add(name, mute).map_err(|e| format!("Failed to save mutes: {}", e))?;
+            notify(state, name, format!("You have been muted: {}", reason)).await;
+            Ok(Some(format!("Muted {}", name)))
+        },
+        ("unmute", [name]) => {
+            if !state.mutes.remove(name).map_err(|e| format!("Failed to save mutes: {}", e))? {
+                return Err(format!("{} is not muted", name))
+            }
+            notify(state, name, String::from("You can chat again")).await;
+            Ok(Some(format!("Unmuted {}", name)))
+        },
+        ("kick", [name, reason @ ..]) => {
+            let player = find_player(state, name)?;
+            let reason = if reason.is_empty() { String::from("Kicked by an operator") } else { reason.join(" ") };
+            state.clients.get(player).unwrap().kick(&reason);
+            Ok(Some(format!("Kicked {}", name)))
+        },
+        ("ban", [name, reason @ ..]) => {
+            let reason = if reason.is_empty() { String::from("Banned by an operator") } else { reason.join(" ") };
+            let ban = Ban { reason: reason.clone(), by: issuer_name.to_string(), since: unix_time() };
+            state.bans.add(name, ban).map_err(|e| format!("Failed to save bans: {}", e))?;
+            if let Some(client) = state.clients.find(name) {
+                client.kick(&format!("You are banned: {}", reason));
+            }
+            Ok(Some(format!("Banned {}", name)))
+        },
+        ("unban", [name]) => {
+            match state.bans.remove(name).map_err(|e| format!("Failed to save bans: {}", e))? {
+                true => Ok(Some(format!("Unbanned {}", name))),
+                false => Err(format!("{} is not banned", name))
+            }
+        },
+        ("ban-ip", [target, reason @ ..]) => {
+            // an online player's name bans whatever address they are on
+            let address = match target.parse::<IpAddr>() {
+                Ok(address) => address,
+                Err(_) => state.clients.get(find_player(state, target)?).unwrap().address
+            };
+            let reason = if reason.is_empty() { String::from("Banned by an operator") } else { reason.join(" ") };
+            let ban = Ban { reason: reason.clone(), by: issuer_name.to_string(), since: unix_time() };
+            state.ip_bans.add(&address.to_string(), ban).map_err(|e| format!("Failed to save ip bans: {}", e))?;
+            state.sync_ip_bans();
+            for client in state.clients.clients().filter(|c| c.address == address) {
+                client.kick(&format!("Your address is banned: {}", reason));
+            }
+            Ok(Some(format!("Banned address {}", address)))
+        },
+        ("unban-ip", [address]) => {
+            let address = address.parse::<IpAddr>().map_err(|_| format!("Invalid address {}", address))?;
+            if !state.ip_bans.remove(&address.to_string()).map_err(|e| format!("Failed to save ip bans: {}", e))? {
+                return Err(format!("{} is not banned", address))
+            }
+            state.sync_ip_bans();
+            Ok(Some(format!("Unbanned address {}", address)))
+        },
+        ("whitelist", [action]) if *action == "on" || *action == "off" => {
+            let enabled = *action == "on";
+            state.whitelist.set_enabled(enabled).map_err(|e| format!("Failed to save whitelist: {}", e))?;
+            state.enforce_lists();
+            Ok(Some(format!("Whitelist is now {}", action)))
+        },
+        ("whitelist", ["list"]) => {
+            let status = if state.whitelist.enabled() { "on" } else { "off" };
+            let names = state.whitelist.names();
+            Ok(Some(format!("Whitelist is {}, {} listed: {}", status, names.len(), names.join(", "))))
+        },
+        ("whitelist", ["add", name]) => {
+            match state.whitelist.add(name).map_err(|e| format!("Failed to save whitelist: {}", e))? {
+                true => Ok(Some(format!("Added {} to the whitelist", name))),
+                false => Err(format!("{} is already on the whitelist", name))
+            }
+        },
+        ("whitelist", ["remove", name]) => {
+            if !state.whitelist.remove(name).map_err(|e| format!("Failed to save whitelist: {}", e))? {
+                return Err(format!("{} is not on the whitelist", name))
+            }
+            state.enforce_lists();
+            Ok(Some(format!("Removed {} from the whitelist", name)))
+        },
+        ("reload", []) => {
+            let errors = state.reload_lists(false);
+            match errors.is_empty() {
+                true => Ok(Some(String::from("Reloaded ops, bans, the whitelist and mutes"))),
+                false => Err(errors.join("\n"))
+            }
+        },
+        ("seed", []) => Ok(Some(format!("Seed: {}", state.world.seed))),
+        ("setspawn", args) if args.len() <= 1 => {
+            let client = match issuer {
+                Issuer::Player(id) => state.clients.get(id).unwrap(),
+                Issuer::Console => return Err(String::from("Only players can set the spawn point to where they stand"))
+            };
+            let radius = match args.first() {
+                Some(radius) => radius.parse::<u32>().map_err(|_| format!("Invalid radius {}", radius))?,
+                None => state.world.spawn.radius
+            };
+            let spawn = SpawnPoint { x: client.x.round() as i32, z: client.z.round() as i32, radius };
+            // finding the fallback spot may generate chunks
+            block_in_place(|| state.world.set_spawn(spawn)).map_err(|e| format!("Failed to save spawn point: {}", e))?;
+            Ok(Some(format!("Spawn point set to {}, {} with radius {}", spawn.x, spawn.z, spawn.radius)))
+        },
+        ("chunks", []) => {
+            let stats = state.world.chunk_stats();
+            Ok(Some(format!(
+                "{} of at most {} chunks loaded, {} unsaved, ~{} MiB, {} region files open",
+                stats.loaded, state.world.max_loaded_chunks, stats.dirty,
+                stats.memory_bytes / (1024 * 1024), stats.open_regions
+            )))
+        },
+        ("save-all", []) => {
+            let saved = state.save();
+            Ok(Some(format!("Saved {} chunks", saved)))
+        },
+        ("op", [name, rest @ ..]) if rest.len() <= 1 => {
+            let new_level = match rest.first() {
+                Some(new_level) => new_level.parse::<u8>().map_err(|_| format!("Invalid level {}", new_level))?,
+                None => LEVEL_MODERATOR
+            };
+            if new_level == LEVEL_PLAYER || new_level > level {
+                return Err(format!("Level must be from {} to {}", LEVEL_MODERATOR, level))
+            }
+            state.ops.set(name, new_level).map_err(|e| format!("Failed to save ops: {}", e))?;
+            notify(state, name, format!("You are now an operator with level {}", new_level)).await;
+            Ok(Some(format!("Made {} an operator with level {}", name, new_level)))
+        },
+        ("deop", [name]) => {
+            if !state.ops.remove(name).map_err(|e| format!("Failed to save ops: {}", e))? {
+                return Err(format!("{} is not an operator", name))
+            }
+            notify(state, name, String::from("You are no longer an operator")).await;
+            Ok(Some(format!("{} is no longer an operator", name)))
+        },
+        ("stop", []) => {
+            state.stop.notify_one();
+            Ok(None)
+        },
+        _ => Err(usage())
+    }
+}
+
+// sends a whisper to the player it is for, back to whoever sent it so their
+// client can show it, and to any staff spying, then points /r both ways
+async fn whisper(state: &mut State, issuer: Issuer, issuer_name: &str, to: PlayerId, content: &str) -> CommandResult {
+    let from = match issuer {
+        Issuer::Player(id) => id,
+        Issuer::Console => PlayerId::SERVER
+    };
+    if from == to {
+        return Err(String::from("You cannot whisper to yourself"))
+    }
+    check_not_muted(state, issuer, issuer_name)?;
+
+    let to_name = state.clients.get(to).unwrap().display_name();
+    let content = filter_words(content, &state.chat.filtered_words);
+    log!(Info, "\u{001b}[33m[{} -> {}] {}\u{001b}[0m", issuer_name, to_name, content);
+    state.history.record(&format!("[{} -> {}] {}", issuer_name, to_name, content), false);
+
+    let mut recipients = vec![to];
+    if issuer != Issuer::Console {
+        recipients.push(from);
+    }
+    let spies: Vec<PlayerId> = state.clients.clients()
+        .filter(|c| c.spying && !recipients.contains(&c.id) && state.permission_level(c.id) >= LEVEL_MODERATOR)
+        .map(|c| c.id)
+        .collect();
+    recipients.extend(spies);
+    let message = RustyCraftMessage::Whisper { from: issuer_name.to_string(), to: to_name.clone(), content: content.clone() };
+    state.send_to(&recipients, message, from).await;
+
+    if let Some(client) = state.clients.get_mut(to) {
+        client.reply_to = Some(from).filter(|from| *from != PlayerId::SERVER);
+    }
+    match issuer {
+        Issuer::Player(id) => {
+            if let Some(client) = state.clients.get_mut(id) {
+                client.reply_to = Some(to);
+            }
+            Ok(None)
+        },
+        Issuer::Console => Ok(Some(format!("[You -> {}] {}", to_name, content)))
+    }
+}
+
+// everyone who should receive a channel's messages; staff who have
+// since lost their level no longer hear the staff channel
+fn channel_members(state: &State, channel: &str) -> Vec<PlayerId> {
+    state.clients.clients()
+        .filter(|c| c.channels.contains(channel))
+        .filter(|c| channel != STAFF_CHANNEL || state.permission_level(c.id) >= LEVEL_MODERATOR)
+        .map(|c| c.id)
+        .collect()
+}
+
+fn channel_name(name: &str) -> Result<String, String> {
+    let valid = name.chars().count() <= MAX_CHANNEL_NAME_LENGTH
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(format!(
+            "Channel names are up to {} letters, digits, _ or -", 
+            MAX_CHANNEL_NAME_LENGTH
+        ))
+    }
+    Ok(name.to_lowercase())
+}
+
+fn player_only(issuer: Issuer) -> Result<PlayerId, String> {
+    match issuer {
+        Issuer::Player(id) => Ok(id),
+        Issuer::Console => Err(String::from("Only players can use this command"))
+    }
+}
+
+fn check_not_muted(state: &State, issuer: Issuer, issuer_name: &str) -> Result<(), String> {
+    // the console is never muted
+    if issuer == Issuer::Console {
+        return Ok(())
+    }
+    match state.mutes.get(issuer_name) {
+        Some(mute) => Err(format!("You are muted: {}", mute.reason)),
+        None => Ok(())
+    }
+}
+
+fn find_player(state: &State, name: &str) -> Result<PlayerId, String> {
+    state.clients.find(name).map(|c| c.id).ok_or_else(|| format!("{} is not online", name))
+}
+
+fn parse_position(x: &str, y: &str, z: &str) -> Result<(f32, f32, f32), String> {
+    let parse = |value: &str| match value.parse::<f32>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!("Invalid coordinate {}", value))
+    };
+    let position = (parse(x)?, parse(y)?, parse(z)?);
+    if position.1 < 0.0 || position.1 >= CHUNK_HEIGHT as f32 {
+        return Err(format!("y must be from 0 to {}", CHUNK_HEIGHT - 1))
+    }
+    Ok(position)
+}
+
+// clients too old for PositionCorrection would never learn they were moved
+async fn teleport(state: &mut State, id: PlayerId, (x, y, z): (f32, f32, f32)) -> Result<(), String> {
+    let correction = RustyCraftMessage::PositionCorrection { x, y, z };
+    if let Some(client) = state.clients.get_mut(id) {
+        if !client.understands(&correction) {
+            return Err(format!("{}'s client is too old to be teleported", client.display_name()))
+        }
+        client.x = x;
+        client.y = y;
+        client.z = z;
+        client.last_move = Instant::now();
+    }
+    state.send_or_drop(id, serialize_event(PlayerId::SERVER, correction)).await;
+    state.send_to_viewers(id, RustyCraftMessage::PlayerPosition { x, y, z }).await;
+    state.update_interest(id).await;
+    Ok(())
+}
+
+// tells a player about a change to them if they are online
+async fn notify(state: &mut State, name: &str, content: String) {
+    if let Some(id) = state.clients.find(name).map(|c| c.id) {
+        let message = RustyCraftMessage::SystemMessage { content };
+        state.send_or_drop(id, serialize_event(PlayerId::SERVER, message)).await;
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}h {}m {}s", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,349 @@
+use std::{fs, io, net::{IpAddr, Ipv4Addr}, path::{Path, PathBuf}, time::Duration};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::rustycraft::{chunk::DEFAULT_TERRAIN_AMPLITUDE, files::write_atomic, world::{DEFAULT_MAX_LOADED_CHUNKS, DEFAULT_SPAWN_RADIUS}};
+
+use super::{chat::ChatConfig, client::OverflowPolicy, console::LogLevel, history::HistoryConfig, movement::MovementConfig, names::{DEFAULT_NAME_CHARACTERS, NameConfig}, placement::PlacementConfig, streaming::ChunkRequestConfig};
+
+pub const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+// settings from server.toml, overridden by command line flags
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub world: String,
+    // only used when the world is first created
+    pub seed: Option<u32>,
+    pub max_players: usize,
+    pub max_connections_per_ip: usize,
+    // seconds a connection has to send PlayerJoin
+    pub join_timeout: u64,
+    pub motd: String,
+    // in chunks
+    pub view_distance: u32,
+    // most chunks streamed to each player per tick
+    pub chunks_per_tick: usize,
+    // most chunks one GetChunks may ask for
+    pub max_chunk_request: usize,
+    // GetChunks requests per second each player can keep up
+    pub chunk_request_rate: f32,
+    // GetChunks requests each player can send at once
+    pub chunk_request_burst: u32,
+    // chunks kept in memory before the least recently used are unloaded
+    pub max_loaded_chunks: usize,
+    // in seconds
+    pub autosave_interval: u64,
+    pub log_level: LogLevel,
+    pub overflow_policy: OverflowPolicy,
+    // whether moves are checked for speed and collisions
+    pub movement_checks: bool,
+    // in blocks per second
+    pub max_horizontal_speed: f32,
+    pub max_rise_speed: f32,
+    pub max_fall_speed: f32,
+    // extra blocks allowed on every move for network jitter
+    pub movement_tolerance: f32,
+    // furthest a player can edit a block from, in blocks
+    pub max_reach: f32,
+    // in characters
+    pub max_name_length: usize,
+    // every character a player name may use
+    pub name_characters: String,
+    // in characters
+    pub chat_max_length: usize,
+    // chat messages per second each player can keep up
+    pub chat_rate: f32,
+    // chat messages each player can send at once
+    pub chat_burst: u32,
+    pub filtered_words: Vec<String>,
+    // chat lines kept for /history
+    pub chat_history_size: usize,
+    // chat lines sent to players as they join
+    pub chat_backlog: usize,
+    // size chat.log reaches before it is rotated, in bytes
+    pub chat_log_max_bytes: u64,
+    // rotated chat logs to keep
+    pub chat_log_files: usize,
+    pub terrain_amplitude: f32
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let movement = MovementConfig::default();
+        ServerConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 25566,
+            world: String::from("world"),
+            seed: None,
+            max_players: 20,
+            max_connections_per_ip: 4,
+            join_timeout: 10,
+            motd: String::from("A RustyCraft server"),
+            view_distance: 8,
+            chunks_per_tick: 4,
+            max_chunk_request: 1089,
+            chunk_request_rate: 2.0,
+            chunk_request_burst: 4,
+            max_loaded_chunks: DEFAULT_MAX_LOADED_CHUNKS,
+            autosave_interval: 60,
+            log_level: LogLevel::Info,
+            overflow_policy: OverflowPolicy::DropPositionUpdates,
+            movement_checks: movement.enabled,
+            max_horizontal_speed: movement.max_horizontal_speed,
+            max_rise_speed: movement.max_rise_speed,
+            max_fall_speed: movement.max_fall_speed,
+            movement_tolerance: movement.tolerance,
+            max_reach: PlacementConfig::default().max_reach,
+            max_name_length: 30,
+            name_characters: String::from(DEFAULT_NAME_CHARACTERS),
+            chat_max_length: 256,
+            chat_rate: 1.0,
+            chat_burst: 5,
+            filtered_words: Vec::new(),
+            chat_history_size: 200,
+            chat_backlog: 10,
+            chat_log_max_bytes: 1024 * 1024,
+            chat_log_files: 5,
+            terrain_amplitude: DEFAULT_TERRAIN_AMPLITUDE
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Server for RustyCraft", version)]
+pub struct Cli {
+    // positional forms kept for older scripts
+    #[arg(value_name = "PORT", conflicts_with = "port", help = "Same as --port")]
+    port_arg: Option<u16>,
+    #[arg(value_name = "OVERFLOW_POLICY", conflicts_with = "overflow_policy", help = "Same as --overflow-policy")]
+    overflow_policy_arg: Option<OverflowPolicy>,
+
+    #[arg(long, value_name = "PATH", default_value = DEFAULT_CONFIG_PATH, help = "Config file, created with defaults if missing")]
+    config: PathBuf,
+    #[arg(long, value_name = "IP", help = "Address to listen on")]
+    bind: Option<IpAddr>,
+    #[arg(long, help = "Port to listen on")]
+    port: Option<u16>,
+    #[arg(long, value_name = "NAME", help = "World to load from worlds/<NAME>, created if missing")]
+    world: Option<String>,
+    #[arg(long, help = "Seed for a newly created world")]
+    seed: Option<u32>,
+    #[arg(long, help = "Most players allowed online at once")]
+    max_players: Option<usize>,
+    #[arg(long, help = "Message shown to players when they join")]
+    motd: Option<String>,
+    #[arg(long, value_name = "CHUNKS", help = "How far players can see, in chunks")]
+    view_distance: Option<u32>,
+    #[arg(long, value_name = "SECONDS", help = "Seconds between autosaves")]
+    autosave_interval: Option<u64>,
+    #[arg(long, help = "One of error, warn, info or debug")]
+    log_level: Option<LogLevel>,
+    #[arg(long, help = "What to do when a client's outbound queue fills: drop, disconnect or block")]
+    overflow_policy: Option<OverflowPolicy>
+}
+
+impl ServerConfig {
+    // reads the config file named on the command line, writing the defaults
+    // there first if it does not exist, then applies command line flags
+    pub fn load(cli: Cli) -> Result<ServerConfig, String> {
+        let mut config = match fs::read_to_string(&cli.config) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| format!("Invalid config file {}: {}", cli.config.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let config = ServerConfig::default();
+                config.save(&cli.config)
+                    .map_err(|e| format!("Failed to write default config to {}: {}", cli.config.display(), e))?;
+                config
+            },
+            Err(e) => return Err(format!("Failed to read config file {}: {}", cli.config.display(), e))
+        };
+
+        if let Some(bind) = cli.bind {
+            config.bind_address = bind;
+        }
+        if let Some(port) = cli.port.or(cli.port_arg) {
+            config.port = port;
+        }
+        if let Some(world) = cli.world {
+            config.world = world;
+        }
+        if cli.seed.is_some() {
+            config.seed = cli.seed;
+        }
+        if let Some(max_players) = cli.max_players {
+            config.max_players = max_players;
+        }
+        if let Some(motd) = cli.motd {
+            config.motd = motd;
+        }
+        if let Some(view_distance) = cli.view_distance {
+            config.view_distance = view_distance;
+        }
+        if let Some(autosave_interval) = cli.autosave_interval {
+            config.autosave_interval = autosave_interval;
+        }
+        if let Some(log_level) = cli.log_level {
+            config.log_level = log_level;
+        }
+        if let Some(overflow_policy) = cli.overflow_policy.or(cli.overflow_policy_arg) {
+            config.overflow_policy = overflow_policy;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let valid_world = !self.world.is_empty()
+            && self.world != "."
+            && self.world != ".."
+            && !self.world.contains(['/', '\\']);
+        if !valid_world {
+            return Err(format!("Invalid world name \"{}\", it must be a plain directory name", self.world))
+        }
+        if self.max_players == 0 {
+            return Err(String::from("max_players must be at least 1"))
+        }
+        if self.max_connections_per_ip == 0 {
+            return Err(String::from("max_connections_per_ip must be at least 1"))
+        }
+        if self.join_timeout == 0 {
+            return Err(String::from("join_timeout must be at least 1 second"))
+        }
+        if !(1..=32).contains(&self.view_distance) {
+            return Err(format!("view_distance must be from 1 to 32 chunks, not {}", self.view_distance))
+        }
+        if !(1..=64).contains(&self.chunks_per_tick) {
+            return Err(format!("chunks_per_tick must be from 1 to 64, not {}", self.chunks_per_tick))
+        }
+        if self.max_chunk_request == 0 {
+            return Err(String::from("max_chunk_request must be at least 1"))
+        }
+        if !(self.chunk_request_rate.is_finite() && self.chunk_request_rate > 0.0) {
+            return Err(format!("chunk_request_rate must be above 0 requests per second, not {}", self.chunk_request_rate))
+        }
+        if self.chunk_request_burst == 0 {
+            return Err(String::from("chunk_request_burst must be at least 1"))
+        }
+        // the chunks around spawn are never unloaded, so there must be room for them
+        let spawn_chunks = ((2 * DEFAULT_SPAWN_RADIUS + 1) * (2 * DEFAULT_SPAWN_RADIUS + 1)) as usize;
+        if self.max_loaded_chunks < spawn_chunks {
+            return Err(format!("max_loaded_chunks must be at least {}, not {}", spawn_chunks, self.max_loaded_chunks))
+        }
+        if self.autosave_interval == 0 {
+            return Err(String::from("autosave_interval must be at least 1 second"))
+        }
+        let speeds = [
+            ("max_horizontal_speed", self.max_horizontal_speed), 
+            ("max_rise_speed", self.max_rise_speed), 
+            ("max_fall_speed", self.max_fall_speed)
+        ];
+        for (key, speed) in speeds {
+            if !(speed.is_finite() && speed > 0.0) {
+                return Err(format!("{} must be above 0 blocks per second, not {}", key, speed))
+            }
+        }
+        if !(self.movement_tolerance.is_finite() && self.movement_tolerance >= 0.0) {
+            return Err(format!("movement_tolerance must be 0 blocks or more, not {}", self.movement_tolerance))
+        }
+        if !(1.0..=64.0).contains(&self.max_reach) {
+            return Err(format!("max_reach must be from 1 to 64 blocks, not {}", self.max_reach))
+        }
+        if !(1..=64).contains(&self.max_name_length) {
+            return Err(format!("max_name_length must be from 1 to 64 characters, not {}", self.max_name_length))
+        }
+        if self.name_characters.is_empty() {
+            return Err(String::from("name_characters cannot be empty"))
+        }
+        if let Some(c) = self.name_characters.chars().find(|c| c.is_control()) {
+            return Err(format!("name_characters cannot contain control characters such as {:?}", c))
+        }
+        if !(1..=4096).contains(&self.chat_max_length) {
+            return Err(format!("chat_max_length must be from 1 to 4096 characters, not {}", self.chat_max_length))
+        }
+        if !(self.chat_rate.is_finite() && self.chat_rate > 0.0) {
+            return Err(format!("chat_rate must be above 0 messages per second, not {}", self.chat_rate))
+        }
+        if self.chat_burst == 0 {
+            return Err(String::from("chat_burst must be at least 1"))
+        }
+        if self.chat_backlog > self.chat_history_size {
+            return Err(format!(
+                "chat_backlog cannot be more than chat_history_size ({}), not {}", 
+                self.chat_history_size, self.chat_backlog
+            ))
+        }
+        if self.chat_log_max_bytes < 1024 {
+            return Err(format!("chat_log_max_bytes must be at least 1024, not {}", self.chat_log_max_bytes))
+        }
+        if self.chat_log_files == 0 {
+            return Err(String::from("chat_log_files must be at least 1"))
+        }
+        if !(0.0..=200.0).contains(&self.terrain_amplitude) {
+            return Err(format!("terrain_amplitude must be from 0 to 200, not {}", self.terrain_amplitude))
+        }
+        Ok(())
+    }
+
+    pub fn autosave_interval(&self) -> Duration {
+        Duration::from_secs(self.autosave_interval)
+    }
+
+    pub fn join_timeout(&self) -> Duration {
+        Duration::from_secs(self.join_timeout)
+    }
+
+    pub fn chat(&self) -> ChatConfig {
+        ChatConfig {
+            max_length: self.chat_max_length,
+            rate: self.chat_rate,
+            burst: self.chat_burst as f32,
+            filtered_words: self.filtered_words.clone()
+        }
+    }
+
+    pub fn chunk_requests(&self) -> ChunkRequestConfig {
+        ChunkRequestConfig {
+            max_coords: self.max_chunk_request,
+            rate: self.chunk_request_rate,
+            burst: self.chunk_request_burst as f32
+        }
+    }
+
+    pub fn movement(&self) -> MovementConfig {
+        MovementConfig {
+            enabled: self.movement_checks,
+            max_horizontal_speed: self.max_horizontal_speed,
+            max_rise_speed: self.max_rise_speed,
+            max_fall_speed: self.max_fall_speed,
+            tolerance: self.movement_tolerance,
+            ..MovementConfig::default()
+        }
+    }
+
+    pub fn placement(&self) -> PlacementConfig {
+        PlacementConfig { max_reach: self.max_reach }
+    }
+
+    pub fn history(&self) -> HistoryConfig {
+        HistoryConfig {
+            size: self.chat_history_size,
+            backlog: self.chat_backlog,
+            log_max_bytes: self.chat_log_max_bytes,
+            log_files: self.chat_log_files
+        }
+    }
+
+    pub fn names(&self) -> NameConfig {
+        NameConfig { max_length: self.max_name_length, allowed_characters: self.name_characters.clone() }
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = toml::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let contents = format!("# RustyCraft server settings, command line flags take precedence\n\n{}", contents);
+        write_atomic(path, contents.as_bytes())
+    }
+}
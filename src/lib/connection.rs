@@ -0,0 +1,307 @@
+use std::{io, sync::Arc};
+use futures::{SinkExt, StreamExt};
+use tokio::{net::{TcpListener, TcpStream, tcp::OwnedWriteHalf}, sync::{Notify, mpsc}, time::timeout};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use super::{admission::{Admission, Admitted}, client::{ClientConfig, Outbound, Outgoing}, codec::MessageCodec, event::serialize_event, events::RustyCraftMessage, game::GameEvent, handshake::{LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION, ServerInfo, for_version, negotiate}, player_id::PlayerId};
+
+// most lines buffered before a flush
+const WRITE_BATCH_SIZE: usize = 256;
+
+// starts a connection task for every connection admission allows,
+// turning the rest away with a Kick saying why
+pub async fn accept(listener: TcpListener, admission: Arc<Admission>, config: ClientConfig, info: Arc<ServerInfo>, events: mpsc::Sender<GameEvent>) {
+    // 0 is reserved for the server itself
+    let mut next_id = 1;
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log!(Error, "Error: {}", e);
+                continue
+            }
+        };
+        let admitted = match admission.admit(address.ip()) {
+            Ok(admitted) => admitted,
+            Err(reason) => {
+                log!(Debug, "Refused connection from {}: {}", address, reason);
+                tokio::spawn(reject(stream, reason, config));
+                continue
+            }
+        };
+        log!(Debug, "Accepted connection from {}", address);
+        let _ = stream.set_nodelay(true);
+        let id = PlayerId(next_id);
+        next_id = next_id.checked_add(1).unwrap_or(1);
+        tokio::spawn(handle_connection(stream, id, config, info.clone(), events.clone(), admitted));
+    }
+}
+
+// single task per connection: forwards decoded messages to the
+// game task and writes whatever the game task queues for this client
+pub async fn handle_connection(stream: TcpStream, id: PlayerId, config: ClientConfig, info: Arc<ServerInfo>, events: mpsc::Sender<GameEvent>, admitted: Admitted) {
+    let (sender, mut outgoing) = mpsc::channel(config.queue_capacity);
+    let closer = Arc::new(Notify::new());
+    let outbound = Outbound::new(sender, closer.clone(), config.overflow_policy);
+    if events.send(GameEvent::Connected { id, address: admitted.ip(), outbound }).await.is_err() {
+        return
+    }
+
+    let (read_half, write_half) = stream.into_split();
+    let mut reader = FramedRead::new(read_half, MessageCodec::new(config.max_line_length));
+    let mut writer = FramedWrite::new(write_half, MessageCodec::new(config.max_line_length));
+
+    // Hello is only accepted as the very first message
+    let mut awaiting_hello = true;
+    let mut protocol_version = LEGACY_PROTOCOL_VERSION;
+    let reason = loop {
+        tokio::select! {
+            frame = reader.next() => match frame {
+                None => break None,
+                Some(Ok(RustyCraftMessage::Hello { protocol_version: hello_version, client_name, capabilities })) => {
+                    // anything that sends Hello understands Kick
+                    if !awaiting_hello {
+                        kick(&mut writer, "Hello must be the first message", PROTOCOL_VERSION, &config).await;
+                        break Some(String::from("sent Hello after the handshake"))
+                    }
+                    awaiting_hello = false;
+
+                    let (welcome, framing) = match negotiate(&info, hello_version, &capabilities) {
+                        Ok(negotiated) => negotiated,
+                        Err(reason) => {
+                            kick(&mut writer, &reason, PROTOCOL_VERSION, &config).await;
+                            break Some(format!("rejected {:?}: {}", client_name, reason))
+                        }
+                    };
+                    protocol_version = hello_version;
+                    if events.send(GameEvent::Negotiated { id, protocol_version }).await.is_err() {
+                        break None
+                    }
+
+                    // welcome in the old framing, then switch both directions
+                    let welcome = serialize_event(PlayerId::SERVER, welcome);
+                    match timeout(config.write_timeout, writer.send(welcome)).await {
+                        Ok(Ok(())) => {},
+                        _ => break Some(String::from("failed to send Welcome"))
+                    }
+                    reader.decoder_mut().set_framing(framing);
+                    writer.encoder_mut().set_framing(framing);
+                },
+                Some(Ok(message)) => {
+                    awaiting_hello = false;
+                    if events.send(GameEvent::Message { id, message }).await.is_err() {
+                        break None
+                    }
+                },
+                Some(Err(e)) => {
+                    kick(&mut writer, &e.to_string(), protocol_version, &config).await;
+                    break Some(e.to_string())
+                }
+            },
+            first = outgoing.recv() => {
+                let first = match first {
+                    Some(first) => first,
+                    None => break None
+                };
+                match timeout(config.write_timeout, write_batch(&mut writer, &mut outgoing, first, protocol_version)).await {
+                    Ok(Ok(false)) => {},
+                    Ok(Ok(true)) => break None,
+                    Ok(Err(e)) => break Some(format!("write failed: {}", e)),
+                    Err(_) => break Some(String::from("write timed out"))
+                }
+            },
+            _ = closer.notified() => break None
+        }
+    };
+
+    let _ = events.send(GameEvent::Disconnected { id, reason }).await;
+}
+
+// writes the first queued item plus whatever else is already waiting
+// and flushes once, returning true if a Close was reached; events are
+// rewritten or skipped for clients on older protocol versions
+async fn write_batch(writer: &mut FramedWrite<OwnedWriteHalf, MessageCodec>, outgoing: &mut mpsc::Receiver<Outgoing>, first: Outgoing, protocol_version: u32) -> io::Result<bool> {
+    let mut next = Some(first);
+    let mut batched = 0;
+    while let Some(item) = next.take() {
+        match item {
+            Outgoing::Event(event) => {
+                if let Some(event) = for_version(event, protocol_version) {
+                    writer.feed(event).await?;
+                }
+            },
+            Outgoing::Close => {
+                writer.flush().await?;
+                return Ok(true)
+            }
+        }
+        batched += 1;
+        if batched < WRITE_BATCH_SIZE {
+            next = outgoing.try_recv().ok();
+        }
+    }
+    writer.flush().await?;
+    Ok(false)
+}
+
+// turns a connection away before it reaches the game task, telling
+// it why in the JSON framing since nothing has been negotiated yet
+pub async fn reject(stream: TcpStream, reason: String, config: ClientConfig) {
+    let (_, write_half) = stream.into_split();
+    let mut writer = FramedWrite::new(write_half, MessageCodec::new(config.max_line_length));
+    kick(&mut writer, &reason, PROTOCOL_VERSION, &config).await;
+}
+
+// best effort, the connection may already be gone
+async fn kick(writer: &mut FramedWrite<OwnedWriteHalf, MessageCodec>, reason: &str, protocol_version: u32, config: &ClientConfig) {
+    let kick = serialize_event(PlayerId::SERVER, RustyCraftMessage::Kick { reason: reason.to_string() });
+    if let Some(kick) = for_version(kick, protocol_version) {
+        let _ = timeout(config.write_timeout, writer.send(kick)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, net::{Ipv4Addr, SocketAddr}, time::Duration};
+    use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines}, net::tcp::OwnedReadHalf, sync::{Barrier, oneshot}, task::JoinSet};
+
+    use super::*;
+    use crate::lib::{config::ServerConfig, events::WorldSummary, game::{self, GameConfig}, state::State};
+
+    const PLAYERS: usize = 300;
+
+    // waits for the next event carrying a message of the given variant
+    async fn next_of(lines: &mut Lines<BufReader<OwnedReadHalf>>, variant: &str) -> serde_json::Value {
+        loop {
+            let line = lines.next_line().await.unwrap().expect("server hung up");
+            let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+            if event["message"].get(variant).is_some() {
+                return event["message"][variant].clone()
+            }
+        }
+    }
+
+    // every player joins, then everyone chats at once and each player
+    // must see every message without anyone being dropped
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn hundreds_of_clients() {
+        let world = format!("test-load-{}", std::process::id());
+        let config = ServerConfig { 
+            world: world.clone(), 
+            max_players: PLAYERS, 
+            max_connections_per_ip: PLAYERS, 
+            view_distance: 1, 
+            ..ServerConfig::default() 
+        };
+        let state = State::new(&config);
+        let info = Arc::new(ServerInfo { 
+            world: WorldSummary { name: world.clone(), seed: state.world.seed, view_distance: config.view_distance } 
+        });
+        let admission = state.admission.clone();
+        let (events, receiver) = mpsc::channel(4096);
+        let game = tokio::spawn(game::run(state, GameConfig::new(&config), receiver));
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(accept(listener, admission, ClientConfig::default(), info, events.clone()));
+
+        let joined = Arc::new(Barrier::new(PLAYERS));
+        let mut players = JoinSet::new();
+        for n in 0..PLAYERS {
+            let joined = joined.clone();
+            players.spawn(async move {
+                let (read_half, mut write_half) = TcpStream::connect(address).await.unwrap().into_split();
+                let mut lines = BufReader::new(read_half).lines();
+                let join = format!("{{\"PlayerJoin\":{{\"name\":\"player{}\"}}}}\n", n);
+                write_half.write_all(join.as_bytes()).await.unwrap();
+                next_of(&mut lines, "ConnectionData").await;
+
+                joined.wait().await;
+                let chat = format!("{{\"ChatMessage\":{{\"content\":\"load test {}\"}}}}\n", n);
+                write_half.write_all(chat.as_bytes()).await.unwrap();
+                let mut heard = 0;
+                while heard < PLAYERS {
+                    let chat = next_of(&mut lines, "ChatMessage").await;
+                    if chat["content"].as_str().unwrap().contains("load test") {
+                        heard += 1;
+                    }
+                }
+                write_half.write_all(b"\"Disconnect\"\n").await.unwrap();
+            });
+        }
+        let finished = timeout(Duration::from_secs(60), async {
+            while let Some(player) = players.join_next().await {
+                player.unwrap();
+            }
+        }).await;
+
+        server.abort();
+        let (done, stopped) = oneshot::channel();
+        let _ = events.send(GameEvent::Shutdown { done }).await;
+        let _ = stopped.await;
+        let _ = game.await;
+        let _ = fs::remove_dir_all(format!("worlds/{}", world));
+        let _ = fs::remove_dir("worlds");
+        assert!(finished.is_ok(), "players did not all finish within a minute");
+    }
+
+    // clients that never send Hello cannot be sent PositionCorrection, so
+    // a rejected move must not leave them stuck where the server last saw them
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn legacy_client_is_not_locked_out() {
+        let world = format!("test-legacy-move-{}", std::process::id());
+        let config = ServerConfig { world: world.clone(), view_distance: 4, ..ServerConfig::default() };
+        let state = State::new(&config);
+        let info = Arc::new(ServerInfo { 
+            world: WorldSummary { name: world.clone(), seed: state.world.seed, view_distance: config.view_distance } 
+        });
+        let admission = state.admission.clone();
+        let (events, receiver) = mpsc::channel(64);
+        let game = tokio::spawn(game::run(state, GameConfig::new(&config), receiver));
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(accept(listener, admission, ClientConfig::default(), info, events.clone()));
+
+        let (read_half, mut walker) = TcpStream::connect(address).await.unwrap().into_split();
+        let mut walker_lines = BufReader::new(read_half).lines();
+        walker.write_all(b"{\"PlayerJoin\":{\"name\":\"walker\"}}\n").await.unwrap();
+        let data = next_of(&mut walker_lines, "ConnectionData").await;
+        let walker_id = data["id"].clone();
+        let (x, y, z) = (data["players"][0][2].as_f64().unwrap(), data["players"][0][3].as_f64().unwrap(), data["players"][0][4].as_f64().unwrap());
+
+        let (read_half, mut watcher) = TcpStream::connect(address).await.unwrap().into_split();
+        let mut watcher_lines = BufReader::new(read_half).lines();
+        watcher.write_all(b"{\"PlayerJoin\":{\"name\":\"watcher\"}}\n").await.unwrap();
+        // everyone online is named, without giving away where they are
+        let data = next_of(&mut watcher_lines, "ConnectionData").await;
+        assert_eq!(data["players"][1][0], walker_id);
+        assert_eq!(data["players"][1][1], "walker");
+        assert_eq!(data["players"][1][2], 0.0);
+
+        // far too fast, then a small step on from there
+        let jump = format!("{{\"PlayerPosition\":{{\"x\":{},\"y\":{},\"z\":{}}}}}\n", x + 20.0, y + 5.0, z);
+        walker.write_all(jump.as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let step = format!("{{\"PlayerPosition\":{{\"x\":{},\"y\":{},\"z\":{}}}}}\n", x + 20.25, y + 5.0, z);
+        walker.write_all(step.as_bytes()).await.unwrap();
+
+        let seen = timeout(Duration::from_secs(10), async {
+            loop {
+                let line = watcher_lines.next_line().await.unwrap().expect("server hung up");
+                let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let position = &event["message"]["PlayerPosition"];
+                if event["sender"] == walker_id && position["x"].as_f64().is_some_and(|moved| (moved - (x + 20.25)).abs() < 0.01) {
+                    break
+                }
+            }
+        }).await;
+
+        server.abort();
+        let (done, stopped) = oneshot::channel();
+        let _ = events.send(GameEvent::Shutdown { done }).await;
+        let _ = stopped.await;
+        let _ = game.await;
+        let _ = fs::remove_dir_all(format!("worlds/{}", world));
+        let _ = fs::remove_dir("worlds");
+        assert!(seen.is_ok(), "the watcher never saw the walker move on from the rejected spot");
+    }
+}
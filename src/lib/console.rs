@@ -0,0 +1,148 @@
+use std::{fmt, str::FromStr, sync::{Mutex, atomic::{AtomicU8, Ordering}}, thread};
+use rustyline::{Config, DefaultEditor, ExternalPrinter, error::ReadlineError};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use super::game::GameEvent;
+
+// prints a line of server output at a log level, without
+// breaking whatever is being typed into the console
+#[macro_export]
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {
+        if $crate::lib::console::LogLevel::$level <= $crate::lib::console::log_level() {
+            $crate::lib::console::print(format!($($arg)*))
+        }
+    };
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error = 0,
+    Warn,
+    Info,
+    Debug
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("unknown log level '{}', expected error, warn, info or debug", s))
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+pub fn log_level() -> LogLevel {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LogLevel::Error,
+        1 => LogLevel::Warn,
+        2 => LogLevel::Info,
+        _ => LogLevel::Debug
+    }
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// set while the console is running, so output goes above the prompt
+static PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
+
+#[cfg(unix)]
+static SAVED_TERMINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+pub fn print(line: String) {
+    let mut printer = PRINTER.lock().unwrap_or_else(|e| e.into_inner());
+    let failed = match printer.as_mut() {
+        Some(printer) => printer.print(line.clone() + "\n").is_err(),
+        None => true
+    };
+    if failed {
+        println!("{}", line);
+    }
+}
+
+// reads commands from stdin on a thread of its own and hands them
+// to the game task; stops quietly when stdin is closed
+pub fn spawn(events: mpsc::Sender<GameEvent>) {
+    save_terminal();
+    thread::spawn(move || {
+        // keep ctrl-c as SIGINT so it still stops the server
+        let config = Config::builder().enable_signals(true).build();
+        let mut editor = match DefaultEditor::with_config(config) {
+            Ok(editor) => editor,
+            Err(e) => {
+                log!(Error, "\u{001b}[31mConsole unavailable: {}\u{001b}[0m", e);
+                return
+            }
+        };
+        if let Ok(printer) = editor.create_external_printer() {
+            *PRINTER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(printer));
+        }
+
+        loop {
+            match editor.readline("> ") {
+                Ok(line) => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let _ = editor.add_history_entry(line.as_str());
+                    if events.blocking_send(GameEvent::Console { line }).is_err() {
+                        break
+                    }
+                },
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    log!(Error, "\u{001b}[31mConsole stopped: {}\u{001b}[0m", e);
+                    break
+                }
+            }
+        }
+        *PRINTER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    });
+}
+
+#[cfg(unix)]
+fn save_terminal() {
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // fails harmlessly when stdin is not a terminal
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } == 0 {
+        *SAVED_TERMINAL.lock().unwrap_or_else(|e| e.into_inner()) = Some(unsafe { termios.assume_init() });
+    }
+}
+
+#[cfg(not(unix))]
+fn save_terminal() {}
+
+// the console thread is usually mid-read when the server exits,
+// leaving the terminal in raw mode unless it is put back
+pub fn restore_terminal() {
+    *PRINTER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    #[cfg(unix)]
+    if let Some(termios) = SAVED_TERMINAL.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) };
+        println!();
+    }
+}
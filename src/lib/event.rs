@@ -1,12 +1,199 @@
+use std::sync::{Arc, OnceLock};
+use bincode::{DefaultOptions, Options};
+use bytes::Bytes;
 use crate::lib::events::RustyCraftMessage;
 use serde::{Serialize, Deserialize};
 
+use super::player_id::PlayerId;
+
 #[derive(Serialize, Deserialize)]
 pub struct RustyCraftEvent {
-    sender: String,
-    message: RustyCraftMessage
+    pub sender: PlayerId,
+    pub message: RustyCraftMessage
+}
+
+// an event plus its encodings, each produced at most once
+// and shared by every client the event is sent to
+pub struct EncodedEvent {
+    event: RustyCraftEvent,
+    json: OnceLock<Bytes>,
+    binary: OnceLock<Bytes>
+}
+
+impl EncodedEvent {
+    pub fn sender(&self) -> PlayerId {
+        self.event.sender
+    }
+
+    pub fn message(&self) -> &RustyCraftMessage {
+        &self.event.message
+    }
+
+    pub fn json(&self) -> &Bytes {
+        self.json.get_or_init(|| Bytes::from(serde_json::to_vec(&self.event).unwrap()))
+    }
+
+    pub fn binary(&self) -> &Bytes {
+        self.binary.get_or_init(|| Bytes::from(DefaultOptions::new().serialize(&self.event).unwrap()))
+    }
+}
+
+pub fn serialize_event(sender: PlayerId, message: RustyCraftMessage) -> Arc<EncodedEvent> {
+    Arc::new(EncodedEvent { event: RustyCraftEvent { sender, message }, json: OnceLock::new(), binary: OnceLock::new() })
 }
 
-pub fn serialize_event(sender: String, message: RustyCraftMessage) -> String {
-    serde_json::to_string(&RustyCraftEvent { sender, message }).unwrap()
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, BytesMut};
+    use tokio_util::codec::Decoder;
+
+    use super::*;
+    use crate::{lib::{codec::{Framing, MessageCodec}, direction::Direction, events::{ServerFeatures, WorldSummary}}, rustycraft::block_type::BlockType};
+
+    // one of every variant, in declaration order
+    fn samples() -> Vec<RustyCraftMessage> {
+        vec![
+            RustyCraftMessage::Movement { direction: Direction::Backward },
+            RustyCraftMessage::PlayerJoin { name: String::from("Steve") },
+            RustyCraftMessage::PlayerInit { name: String::from("Steve"), x: 1.5, y: -2.25, z: 1e-3 },
+            RustyCraftMessage::PlayerDirection { yaw: -90.0, pitch: 45.5 },
+            RustyCraftMessage::PlayerPosition { x: 0.1, y: 80.0, z: -1234.5 },
+            RustyCraftMessage::SetBlock { block: BlockType::Brick, world_x: -1, world_y: 255, world_z: i32::MAX },
+            RustyCraftMessage::GetChunks { coords: vec![(0, 0), (-3, i32::MIN)] },
+            RustyCraftMessage::ChatMessage { content: String::from("héllo \"world\"\n") },
+            RustyCraftMessage::ConnectionData { 
+                id: PlayerId(7), 
+                players: vec![(PlayerId(7), String::from("Steve"), 1.0, 2.0, 3.0, 4.0, 5.0), (PlayerId(u32::MAX), String::from("Alex"), 0.0, 0.0, 0.0, 0.0, 0.0)] 
+            },
+            RustyCraftMessage::ChunkData { chunks: vec![(1, -1, String::from("\u{7f}\u{0}\u{1}blocks"))] },
+            RustyCraftMessage::Kick { reason: String::from("Server closed") },
+            RustyCraftMessage::Disconnect,
+            RustyCraftMessage::Hello { protocol_version: 10, client_name: String::from("test"), capabilities: vec![String::from("binary_framing")] },
+            RustyCraftMessage::Welcome { 
+                protocol_version: 10, 
+                features: ServerFeatures { compression: false, binary_framing: true, chunk_format_version: 2 }, 
+                world: WorldSummary { name: String::from("world"), seed: 1234, view_distance: 8 } 
+            },
+            RustyCraftMessage::PositionCorrection { x: 1.0, y: 2.0, z: 3.0 },
+            RustyCraftMessage::BlockRejected { world_x: 1, world_y: 2, world_z: 3, block: BlockType::Air, reason: String::from("Out of reach") },
+            RustyCraftMessage::SystemMessage { content: String::from("Saved") },
+            RustyCraftMessage::NameRejected { name: String::from(" bad"), reason: String::from("Name cannot start or end with whitespace") },
+            RustyCraftMessage::Whisper { from: String::from("Steve"), to: String::from("Alex"), content: String::from("hi") },
+            RustyCraftMessage::ChannelMessage { from: String::from("Steve"), channel: String::from("staff"), content: String::from("hi") },
+            RustyCraftMessage::ChatHistory { lines: vec![String::from("[12:00:00] <Steve> hi"), String::new()] },
+            RustyCraftMessage::SpawnPlayer { name: String::from("Alex"), x: 1.0, y: 2.0, z: 3.0, yaw: 4.0, pitch: 5.0 },
+            RustyCraftMessage::DespawnPlayer,
+            RustyCraftMessage::UnloadChunks { coords: vec![(5, -5)] },
+            RustyCraftMessage::PlayerJoined { name: String::from("Alex") },
+        ]
+    }
+
+    // binary framing encodes the variant index, so this pins every
+    // variant to its position; a new variant fails to compile here
+    // until it is added, which should be at the end
+    fn index(message: &RustyCraftMessage) -> u8 {
+        match message {
+            RustyCraftMessage::Movement { .. } => 0,
+            RustyCraftMessage::PlayerJoin { .. } => 1,
+            RustyCraftMessage::PlayerInit { .. } => 2,
+            RustyCraftMessage::PlayerDirection { .. } => 3,
+            RustyCraftMessage::PlayerPosition { .. } => 4,
+            RustyCraftMessage::SetBlock { .. } => 5,
+            RustyCraftMessage::GetChunks { .. } => 6,
+            RustyCraftMessage::ChatMessage { .. } => 7,
+            RustyCraftMessage::ConnectionData { .. } => 8,
+            RustyCraftMessage::ChunkData { .. } => 9,
+            RustyCraftMessage::Kick { .. } => 10,
+            RustyCraftMessage::Disconnect => 11,
+            RustyCraftMessage::Hello { .. } => 12,
+            RustyCraftMessage::Welcome { .. } => 13,
+            RustyCraftMessage::PositionCorrection { .. } => 14,
+            RustyCraftMessage::BlockRejected { .. } => 15,
+            RustyCraftMessage::SystemMessage { .. } => 16,
+            RustyCraftMessage::NameRejected { .. } => 17,
+            RustyCraftMessage::Whisper { .. } => 18,
+            RustyCraftMessage::ChannelMessage { .. } => 19,
+            RustyCraftMessage::ChatHistory { .. } => 20,
+            RustyCraftMessage::SpawnPlayer { .. } => 21,
+            RustyCraftMessage::DespawnPlayer => 22,
+            RustyCraftMessage::UnloadChunks { .. } => 23,
+            RustyCraftMessage::PlayerJoined { .. } => 24
+        }
+    }
+
+    // messages have no PartialEq, and comparing their JSON also
+    // compares floats exactly
+    fn same(a: &RustyCraftMessage, b: &RustyCraftMessage) -> bool {
+        serde_json::to_value(a).unwrap() == serde_json::to_value(b).unwrap()
+    }
+
+    #[test]
+    fn events_round_trip_as_json() {
+        for sender in [PlayerId::SERVER, PlayerId(1), PlayerId(u32::MAX)] {
+            for message in samples() {
+                let event = serialize_event(sender, message.clone());
+                let decoded: RustyCraftEvent = serde_json::from_slice(event.json()).unwrap();
+                assert_eq!(decoded.sender, sender);
+                assert!(same(&decoded.message, &message), "{:?}", message);
+            }
+        }
+    }
+
+    #[test]
+    fn events_round_trip_as_bincode() {
+        for sender in [PlayerId::SERVER, PlayerId(1), PlayerId(u32::MAX)] {
+            for message in samples() {
+                let event = serialize_event(sender, message.clone());
+                let decoded: RustyCraftEvent = DefaultOptions::new().deserialize(event.binary()).unwrap();
+                assert_eq!(decoded.sender, sender);
+                assert!(same(&decoded.message, &message), "{:?}", message);
+            }
+        }
+    }
+
+    #[test]
+    fn server_sender_is_an_empty_string_in_json() {
+        let event = serialize_event(PlayerId::SERVER, RustyCraftMessage::Disconnect);
+        assert_eq!(&event.json()[..], br#"{"sender":"","message":"Disconnect"}"#);
+        let event = serialize_event(PlayerId(42), RustyCraftMessage::Disconnect);
+        assert_eq!(&event.json()[..], br#"{"sender":"42","message":"Disconnect"}"#);
+
+        let decoded: RustyCraftEvent = serde_json::from_str(r#"{"sender":"","message":"Disconnect"}"#).unwrap();
+        assert_eq!(decoded.sender, PlayerId::SERVER);
+        assert!(serde_json::from_str::<RustyCraftEvent>(r#"{"sender":"x","message":"Disconnect"}"#).is_err());
+    }
+
+    #[test]
+    fn server_sender_is_zero_in_bincode() {
+        let event = serialize_event(PlayerId::SERVER, RustyCraftMessage::Disconnect);
+        // varint sender, then the variant index
+        assert_eq!(&event.binary()[..], &[0, 11]);
+    }
+
+    #[test]
+    fn variant_indexes_are_stable() {
+        for (position, message) in samples().iter().enumerate() {
+            assert_eq!(index(message) as usize, position, "samples are out of order at {:?}", message);
+            let encoded = DefaultOptions::new().serialize(message).unwrap();
+            assert_eq!(encoded[0], index(message), "{:?} moved", message);
+        }
+    }
+
+    #[test]
+    fn messages_decode_from_clients_in_both_framings() {
+        for message in samples() {
+            let mut codec = MessageCodec::new(64 * 1024);
+            let mut src = BytesMut::from(&serde_json::to_vec(&message).unwrap()[..]);
+            src.put_u8(b'\n');
+            assert!(same(&codec.decode(&mut src).unwrap().unwrap(), &message));
+
+            codec.set_framing(Framing::Binary);
+            let payload = DefaultOptions::new().serialize(&message).unwrap();
+            let mut src = BytesMut::new();
+            src.put_u32_le(payload.len() as u32);
+            src.put_slice(&payload);
+            assert!(same(&codec.decode(&mut src).unwrap().unwrap(), &message));
+            assert!(src.is_empty());
+        }
+    }
+}
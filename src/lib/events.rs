@@ -1,8 +1,26 @@
 use serde::{Deserialize, Serialize};
 
 use crate::rustycraft::block_type::BlockType;
-use super::direction::Direction;
+use super::{direction::Direction, player_id::PlayerId};
 
+// features the server agreed to for a connection
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ServerFeatures {
+    pub compression: bool,
+    pub binary_framing: bool,
+    pub chunk_format_version: u32
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct WorldSummary {
+    pub name: String,
+    pub seed: u32,
+    // in chunks
+    pub view_distance: u32
+}
+
+// binary framing encodes the variant index,
+// so new variants must be added at the end
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum RustyCraftMessage {
     Movement { direction: Direction },
@@ -17,10 +35,134 @@ pub enum RustyCraftMessage {
     // echo connection and players id to client
     // to avoid rendering own model and get data
     // for all players currently on the server
-    ConnectionData { id: String, players: Vec<(String, String, f32, f32, f32, f32, f32)> /* (id, name, x, y, z, yaw, pitch) */ },
+    ConnectionData { id: PlayerId, players: Vec<(PlayerId, String, f32, f32, f32, f32, f32)> /* (id, name, x, y, z, yaw, pitch) */ },
 
     // serialized chunk_blocks in the form of Vec<(usize, usize, usize, usize)>
     // stored as string so serialized chunk blocks can be memoized
     ChunkData { chunks: Vec<(i32, i32, String)> },
-    Disconnect
+
+    // sent right before the server closes a connection
+    // so the client can show why it was dropped
+    Kick { reason: String },
+    Disconnect,
+
+    // optional first message, clients that skip it
+    // are treated as speaking the legacy protocol
+    Hello { protocol_version: u32, client_name: String, capabilities: Vec<String> },
+    // server's answer to Hello, sent in JSON; if binary framing
+    // was agreed both sides switch to it right after
+    Welcome { protocol_version: u32, features: ServerFeatures, world: WorldSummary },
+
+    // moves a player, sent when their move was rejected, with the
+    // last position the server accepted, or they were teleported
+    PositionCorrection { x: f32, y: f32, z: f32 },
+
+    // sent to a player whose SetBlock was refused, with the block
+    // actually at those coordinates so the edit can be undone
+    BlockRejected { world_x: i32, world_y: i32, world_z: i32, block: BlockType, reason: String },
+
+    // text from the server itself, such as command output
+    SystemMessage { content: String },
+
+    // answers a PlayerJoin whose name was refused; the connection
+    // stays open so the client can try again with another name
+    NameRejected { name: String, reason: String },
+
+    // private message between two players, sent to both of them and
+    // to staff spying on whispers; from is "Server" for the console
+    Whisper { from: String, to: String, content: String },
+    // chat sent only to the members of a channel
+    ChannelMessage { from: String, channel: String, content: String },
+
+    // earlier public chat and announcements, oldest first, sent
+    // after ConnectionData and in answer to /history
+    ChatHistory { lines: Vec<String> },
+
+    // a player came within view distance and should be shown; until
+    // then only their joining and leaving is sent, not their movement
+    SpawnPlayer { name: String, x: f32, y: f32, z: f32, yaw: f32, pitch: f32 },
+    // the sending player went out of view distance and should be hidden
+    DespawnPlayer,
+
+    // chunks that went out of the player's view distance, which the
+    // server will send again in ChunkData if the player comes back
+    UnloadChunks { coords: Vec<(i32, i32)> },
+
+    // the sending player joined, sent to everyone else so they can put
+    // a name to the player's chat without learning where they are
+    PlayerJoined { name: String },
+}
+
+impl RustyCraftMessage {
+    // protocol version that added the variant; those at 0 are
+    // understood by clients that never send Hello
+    pub fn since_version(&self) -> u32 {
+        match self {
+            RustyCraftMessage::Movement { .. }
+            | RustyCraftMessage::PlayerJoin { .. }
+            | RustyCraftMessage::PlayerInit { .. }
+            | RustyCraftMessage::PlayerDirection { .. }
+            | RustyCraftMessage::PlayerPosition { .. }
+            | RustyCraftMessage::SetBlock { .. }
+            | RustyCraftMessage::GetChunks { .. }
+            | RustyCraftMessage::ChatMessage { .. }
+            | RustyCraftMessage::ConnectionData { .. }
+            | RustyCraftMessage::ChunkData { .. }
+            | RustyCraftMessage::Disconnect => 0,
+            RustyCraftMessage::Kick { .. }
+            | RustyCraftMessage::Hello { .. }
+            | RustyCraftMessage::Welcome { .. } => 1,
+            RustyCraftMessage::PositionCorrection { .. } => 2,
+            RustyCraftMessage::BlockRejected { .. } => 3,
+            RustyCraftMessage::SystemMessage { .. } => 4,
+            RustyCraftMessage::NameRejected { .. } => 6,
+            RustyCraftMessage::Whisper { .. } | RustyCraftMessage::ChannelMessage { .. } => 7,
+            RustyCraftMessage::ChatHistory { .. } => 8,
+            RustyCraftMessage::SpawnPlayer { .. } | RustyCraftMessage::DespawnPlayer => 9,
+            RustyCraftMessage::UnloadChunks { .. } => 10,
+            RustyCraftMessage::PlayerJoined { .. } => 11
+        }
+    }
+
+    // variant name, for logging without the contents
+    pub fn name(&self) -> &'static str {
+        match self {
+            RustyCraftMessage::Movement { .. } => "Movement",
+            RustyCraftMessage::PlayerJoin { .. } => "PlayerJoin",
+            RustyCraftMessage::PlayerInit { .. } => "PlayerInit",
+            RustyCraftMessage::PlayerDirection { .. } => "PlayerDirection",
+            RustyCraftMessage::PlayerPosition { .. } => "PlayerPosition",
+            RustyCraftMessage::SetBlock { .. } => "SetBlock",
+            RustyCraftMessage::GetChunks { .. } => "GetChunks",
+            RustyCraftMessage::ChatMessage { .. } => "ChatMessage",
+            RustyCraftMessage::ConnectionData { .. } => "ConnectionData",
+            RustyCraftMessage::ChunkData { .. } => "ChunkData",
+            RustyCraftMessage::Kick { .. } => "Kick",
+            RustyCraftMessage::Disconnect => "Disconnect",
+            RustyCraftMessage::Hello { .. } => "Hello",
+            RustyCraftMessage::Welcome { .. } => "Welcome",
+            RustyCraftMessage::PositionCorrection { .. } => "PositionCorrection",
+            RustyCraftMessage::BlockRejected { .. } => "BlockRejected",
+            RustyCraftMessage::SystemMessage { .. } => "SystemMessage",
+            RustyCraftMessage::NameRejected { .. } => "NameRejected",
+            RustyCraftMessage::Whisper { .. } => "Whisper",
+            RustyCraftMessage::ChannelMessage { .. } => "ChannelMessage",
+            RustyCraftMessage::ChatHistory { .. } => "ChatHistory",
+            RustyCraftMessage::SpawnPlayer { .. } => "SpawnPlayer",
+            RustyCraftMessage::DespawnPlayer => "DespawnPlayer",
+            RustyCraftMessage::UnloadChunks { .. } => "UnloadChunks",
+            RustyCraftMessage::PlayerJoined { .. } => "PlayerJoined"
+        }
+    }
+
+    // updates that are superseded by the next one from the same
+    // player, so they are safe to drop for a backed up client
+    pub fn is_position_update(&self) -> bool {
+        matches!(
+            self, 
+            RustyCraftMessage::PlayerPosition { .. } 
+            | RustyCraftMessage::PlayerDirection { .. } 
+            | RustyCraftMessage::Movement { .. }
+        )
+    }
 }
\ No newline at end of file
@@ -0,0 +1,422 @@
+use std::{collections::{HashMap, HashSet}, net::IpAddr, time::Duration};
+use tokio::{sync::{mpsc, oneshot}, task::block_in_place, time::{Instant, MissedTickBehavior, interval_at, timeout}};
+
+use crate::rustycraft::block_type::BlockType;
+
+use super::{chat::{filter_words, sanitize}, client::{Client, Outbound}, interest::chunk_of, commands::{Issuer, run_command}, config::ServerConfig, event::serialize_event, events::RustyCraftMessage, movement::{MovementConfig, Violation, check_move}, names::{NameConfig, NameError, check_name}, placement::{PlacementConfig, check_placement}, player_id::PlayerId, state::State, streaming::{ChunkRequestConfig, UNLOAD_MARGIN, in_range}};
+
+// everything connection tasks report to the game task
+pub enum GameEvent {
+    Connected { id: PlayerId, address: IpAddr, outbound: Outbound },
+    // the client's Hello was accepted
+    Negotiated { id: PlayerId, protocol_version: u32 },
+    Message { id: PlayerId, message: RustyCraftMessage },
+    // reason is set when the connection was dropped by an error
+    Disconnected { id: PlayerId, reason: Option<String> },
+    // a line typed into the server console
+    Console { line: String },
+    // save everything and disconnect all clients, then signal done
+    Shutdown { done: oneshot::Sender<()> }
+}
+
+// how long shutdown waits for kicked clients to disconnect
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+// how often connections that have not joined are checked on
+const JOIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// how often the ops, bans, whitelist and mutes files are checked for edits
+const LIST_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// how often chunks are streamed to players
+const STREAM_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone)]
+pub struct GameConfig {
+    pub autosave_interval: Duration,
+    pub movement: MovementConfig,
+    pub placement: PlacementConfig,
+    pub max_players: usize,
+    pub join_timeout: Duration,
+    pub names: NameConfig,
+    pub motd: String,
+    pub chunks_per_tick: usize,
+    pub chunk_requests: ChunkRequestConfig
+}
+
+impl GameConfig {
+    pub fn new(config: &ServerConfig) -> GameConfig {
+        GameConfig { 
+            autosave_interval: config.autosave_interval(), 
+            movement: config.movement(), 
+            placement: config.placement(),
+            max_players: config.max_players,
+            join_timeout: config.join_timeout(),
+            names: config.names(),
+            motd: config.motd.clone(),
+            chunks_per_tick: config.chunks_per_tick,
+            chunk_requests: config.chunk_requests()
+        }
+    }
+}
+
+// central task owning all game state, connection
+// tasks only ever talk to it through messages
+pub async fn run(mut state: State, config: GameConfig, mut events: mpsc::Receiver<GameEvent>) {
+    let mut autosave = interval_at(Instant::now() + config.autosave_interval, config.autosave_interval);
+    autosave.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut join_check = interval_at(Instant::now() + JOIN_CHECK_INTERVAL, JOIN_CHECK_INTERVAL);
+    join_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut list_check = interval_at(Instant::now() + LIST_CHECK_INTERVAL, LIST_CHECK_INTERVAL);
+    list_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut stream = interval_at(Instant::now() + STREAM_INTERVAL, STREAM_INTERVAL);
+    stream.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => event,
+                None => break
+            },
+            _ = autosave.tick() => {
+                let saved = state.save();
+                if saved > 0 {
+                    let stats = state.world.chunk_stats();
+                    log!(
+                        Info,
+                        "\u{001b}[37mAutosaved {} chunks ({} loaded, {} dirty, ~{} MiB)\u{001b}[0m", 
+                        saved, stats.loaded, stats.dirty, stats.memory_bytes / (1024 * 1024)
+                    );
+                }
+                continue
+            },
+            _ = join_check.tick() => {
+                kick_stalled_joins(&state, config.join_timeout);
+                continue
+            },
+            _ = list_check.tick() => {
+                for error in state.reload_lists(true) {
+                    log!(Warn, "\u{001b}[31m{}\u{001b}[0m", error);
+                }
+                continue
+            },
+            _ = stream.tick() => {
+                state.stream_chunks(config.chunks_per_tick).await;
+                continue
+            }
+        };
+
+        match event {
+            GameEvent::Connected { id, address, outbound } => {
+                state.clients.add(Client::new(id, address, outbound));
+            },
+            GameEvent::Negotiated { id, protocol_version } => {
+                if let Some(client) = state.clients.get_mut(id) {
+                    client.protocol_version = protocol_version;
+                }
+            },
+            GameEvent::Message { id, message } => {
+                handle_message(&mut state, &config, id, message).await;
+            },
+            GameEvent::Disconnected { id, reason } => {
+                if let (Some(reason), Some(client)) = (reason, state.clients.get(id)) {
+                    log!(Warn, "\u{001b}[31mDropping {}: {}\u{001b}[0m", client.display_name(), reason);
+                }
+                state.drop_clients(vec![id]).await;
+            },
+            GameEvent::Console { line } => {
+                run_command(&mut state, Issuer::Console, &line).await;
+            },
+            GameEvent::Shutdown { done } => {
+                shutdown(&mut state, &mut events).await;
+                let _ = done.send(());
+                break
+            }
+        }
+    }
+}
+
+// connections that never send PlayerJoin would otherwise hold a slot forever
+fn kick_stalled_joins(state: &State, join_timeout: Duration) {
+    let stalled = state.clients.clients()
+        .filter(|c| c.name.is_none() && c.connected_at.elapsed() >= join_timeout);
+    for client in stalled {
+        log!(Debug, "Connection {} did not join in time", client.id);
+        client.kick("Took too long to join");
+    }
+}
+
+async fn shutdown(state: &mut State, events: &mut mpsc::Receiver<GameEvent>) {
+    let saved = state.save();
+    log!(Info, "\u{001b}[37;1mSaved {} chunks\u{001b}[0m", saved);
+
+    for client in state.clients.clients() {
+        client.kick("Server closed");
+    }
+    // wait for connection tasks to flush the kick and hang up
+    let _ = timeout(SHUTDOWN_GRACE, async {
+        while state.clients.clients().next().is_some() {
+            match events.recv().await {
+                Some(GameEvent::Disconnected { id, .. }) => {
+                    state.clients.remove(id);
+                },
+                Some(_) => {},
+                None => break
+            }
+        }
+    }).await;
+}
+
+async fn handle_message(state: &mut State, config: &GameConfig, id: PlayerId, data: RustyCraftMessage) {
+    let client = match state.clients.get_mut(id) {
+        Some(client) => client,
+        None => return
+    };
+
+    // nothing a connection does reaches other players until it has joined
+    let allowed_before_join = matches!(
+        data,
+        RustyCraftMessage::PlayerJoin { .. } | RustyCraftMessage::GetChunks { .. } | RustyCraftMessage::Disconnect
+    );
+    if client.name.is_none() && !allowed_before_join {
+        return
+    }
+
+    match &data {
+        RustyCraftMessage::GetChunks { coords } => {
+            let limits = &config.chunk_requests;
+            let notice = if coords.len() > limits.max_coords {
+                Some(format!("Chunk requests cannot ask for more than {} chunks", limits.max_coords))
+            } else if !client.chunk_tokens.take(limits.rate, limits.burst) {
+                Some(String::from("You are requesting chunks too quickly, slow down"))
+            } else {
+                None
+            };
+            if let Some(notice) = notice {
+                log!(Debug, "Refused {} chunks asked for by {}: {}", coords.len(), client.display_name(), notice);
+                state.send_system_message(id, notice).await;
+                return
+            }
+
+            // players that have not joined yet can only load around spawn
+            let center = match client.name {
+                Some(_) => chunk_of(client.x, client.z),
+                None => chunk_of(state.world.spawn.x as f32, state.world.spawn.z as f32)
+            };
+            let radius = state.view_distance + UNLOAD_MARGIN;
+            let mut wanted = HashSet::new();
+            let served: Vec<(i32, i32)> = coords.iter()
+                .filter(|coords| in_range(center, **coords, radius) && wanted.insert(**coords))
+                .copied()
+                .collect();
+            if served.len() < coords.len() {
+                log!(Debug, "Skipped {} of the chunks asked for by {}", coords.len() - served.len(), client.display_name());
+            }
+            for coords in served.iter() {
+                client.chunks.mark_sent(*coords);
+            }
+
+            state.send_chunks(id, &served, &mut HashMap::new()).await;
+            block_in_place(|| state.world.unload_excess_chunks());
+        },
+        RustyCraftMessage::PlayerPosition { x, y, z } => {
+            let from = (client.x, client.y, client.z);
+            let world = &state.world;
+            let checked = check_move(|x, y, z| world.get_block(x, y, z), &config.movement, from, (*x, *y, *z), client.last_move.elapsed());
+            if let Err(violation) = checked {
+                client.movement_violations += 1;
+                // put the player back where the server last saw them
+                let correction = RustyCraftMessage::PositionCorrection { x: from.0, y: from.1, z: from.2 };
+                if client.understands(&correction) || matches!(violation, Violation::NotFinite) {
+                    log!(
+                        Warn,
+                        "\u{001b}[31mRejected move by {} ({} so far): {}\u{001b}[0m", 
+                        client.display_name(), client.movement_violations, violation
+                    );
+                    if client.understands(&correction) {
+                        state.send_or_drop(id, serialize_event(PlayerId::SERVER, correction)).await;
+                    }
+                    return
+                }
+                // the client would never hear about a correction and every
+                // later move would be checked from a spot it is not at, so
+                // the server goes along with wherever it says it is
+                log!(
+                    Warn,
+                    "\u{001b}[31mAccepted move by {} that cannot be corrected ({} so far): {}\u{001b}[0m", 
+                    client.display_name(), client.movement_violations, violation
+                );
+            }
+            client.x = *x;
+            client.y = *y;
+            client.z = *z;
+            client.last_move = Instant::now();
+            state.send_to_viewers(id, data.clone()).await;
+            state.update_interest(id).await;
+        },
+        RustyCraftMessage::PlayerJoin { name } => {
+            let joined = client.name.is_some();
+            let checked = match joined {
+                true => Err(NameError::AlreadyJoined),
+                false => check_name(&config.names, name, state.clients.clients().filter_map(|c| c.name.as_deref()))
+            };
+            if let Err(e) = checked {
+                // the name may hold escapes, so it is only logged quoted
+                log!(Info, "\u{001b}[33mRefused name {:?}: {}\u{001b}[0m", name, e);
+                let rejected = RustyCraftMessage::NameRejected { name: name.clone(), reason: e.to_string() };
+                state.send_or_drop(id, serialize_event(PlayerId::SERVER, rejected)).await;
+                return
+            }
+            let client = state.clients.get(id).unwrap();
+            if let Some(reason) = state.refusal(name, client.address) {
+                log!(Warn, "\u{001b}[33m{} tried to join but was refused: {}\u{001b}[0m", name, reason);
+                client.kick(&reason);
+                return
+            }
+            if state.online() >= config.max_players {
+                log!(Info, "\u{001b}[33m{} tried to join but the server is full\u{001b}[0m", name);
+                client.kick("Server is full");
+                return
+            }
+            let client = state.clients.get_mut(id).unwrap();
+            client.name = Some(name.clone());
+            state.admission.set_online(state.online());
+            let client = state.clients.get_mut(id).unwrap();
+            log!(Info, "\u{001b}[33m{} joined the server\u{001b}[0m", client.display_name());
+            // returning players carry on where they left
+            let (x, y, z, yaw, pitch) = match state.world.players.get(name) {
+                Some(saved) => (saved.x, saved.y, saved.z, saved.yaw, saved.pitch),
+                None => {
+                    let (x, y, z) = state.world.find_spawn();
+                    (x, y, z, client.yaw, client.pitch)
+                }
+            };
+            client.x = x;
+            client.y = y;
+            client.z = z;
+            client.yaw = yaw;
+            client.pitch = pitch;
+            client.last_move = Instant::now();
+            // everyone online so their chat can be put a name to, but only
+            // the player's own position; others are sent as a SpawnPlayer
+            // once in range so where everyone else is never leaks
+            let mut players = vec![(id, name.clone(), x, y, z, yaw, pitch)];
+            players.extend(state.clients.clients()
+                .filter(|c| c.id != id && c.name.is_some())
+                .map(|c| (c.id, c.display_name(), 0.0, 0.0, 0.0, 0.0, 0.0)));
+            let others: Vec<PlayerId> = players[1..].iter().map(|player| player.0).collect();
+            let connection_data = RustyCraftMessage::ConnectionData { id, players };
+            let id_message = serialize_event(PlayerId::SERVER, connection_data);
+            state.send_or_drop(id, id_message).await;
+            state.send_to(&others, RustyCraftMessage::PlayerJoined { name: name.clone() }, id).await;
+            let backlog = state.history.backlog();
+            if !backlog.is_empty() {
+                let history = RustyCraftMessage::ChatHistory { lines: backlog };
+                state.send_or_drop(id, serialize_event(PlayerId::SERVER, history)).await;
+            }
+            if !config.motd.is_empty() {
+                let motd = RustyCraftMessage::SystemMessage { content: config.motd.clone() };
+                state.send_or_drop(id, serialize_event(PlayerId::SERVER, motd)).await;
+            }
+            state.history.record(&format!("{} joined the game", name), true);
+            state.update_interest(id).await;
+        },
+        RustyCraftMessage::ChatMessage { content } => {
+            // commands count towards the rate limit as well
+            let notice = if content.chars().count() > state.chat.max_length {
+                Some(format!("Messages cannot be longer than {} characters", state.chat.max_length))
+            } else if !client.chat_tokens.take(state.chat.rate, state.chat.burst) {
+                Some(String::from("You are sending messages too quickly, slow down"))
+            } else {
+                None
+            };
+            if let Some(notice) = notice {
+                state.send_system_message(id, notice).await;
+                return
+            }
+            let content = sanitize(content);
+            if content.is_empty() {
+                return
+            }
+            if content.starts_with('/') {
+                run_command(state, Issuer::Player(id), &content).await;
+                return
+            }
+            let name = client.display_name();
+            if let Some(mute) = state.mutes.get(&name) {
+                let notice = format!("You are muted: {}", mute.reason);
+                state.send_system_message(id, notice).await;
+                return
+            }
+            let content = filter_words(&content, &state.chat.filtered_words);
+            log!(Info, "\u{001b}[33m<{}> {}\u{001b}[0m", name, content);
+            state.history.record(&format!("<{}> {}", name, content), true);
+            state.broadcast(&RustyCraftMessage::ChatMessage { content }, id).await;
+        },
+        RustyCraftMessage::SetBlock { world_x, world_y, world_z, block } => {
+            let position = (client.x, client.y, client.z);
+            let others = state.clients.clients()
+                .filter(|c| c.id != id && c.name.is_some())
+                .map(|c| (c.display_name(), (c.x, c.y, c.z)));
+            let coords = (*world_x, *world_y, *world_z);
+            if let Err(e) = check_placement(&config.placement, position, others, coords, *block) {
+                let current = state.world.get_block(*world_x, *world_y, *world_z).unwrap_or(BlockType::Air);
+                let rejected = RustyCraftMessage::BlockRejected { 
+                    world_x: *world_x, 
+                    world_y: *world_y, 
+                    world_z: *world_z, 
+                    block: current, 
+                    reason: e.to_string() 
+                };
+                state.send_or_drop(id, serialize_event(PlayerId::SERVER, rejected)).await;
+                return
+            }
+            block_in_place(|| {
+                state.world.set_block(*world_x, *world_y, *world_z, *block);
+                state.world.unload_excess_chunks();
+            });
+            let chunk = chunk_of(*world_x as f32, *world_z as f32);
+            state.broadcast_to_chunk(chunk, data.clone(), id).await;
+        },
+        RustyCraftMessage::PlayerDirection { yaw, pitch } => {
+            if !(yaw.is_finite() && pitch.is_finite()) {
+                log!(Warn, "\u{001b}[31mIgnored direction from {} that is not a finite number\u{001b}[0m", client.display_name());
+                return
+            }
+            client.yaw = *yaw;
+            client.pitch = *pitch;
+            state.send_to_viewers(id, data.clone()).await;
+        },
+        RustyCraftMessage::Movement { .. } => {
+            state.send_to_viewers(id, data.clone()).await;
+        },
+        RustyCraftMessage::Disconnect => {
+            state.drop_clients(vec![id]).await;
+        },
+        // these name who said them, so a client sending one is
+        // trying to put words in someone else's mouth
+        RustyCraftMessage::Whisper { .. } | RustyCraftMessage::ChannelMessage { .. } => {
+            log!(Warn, "\u{001b}[31mRejected forged {} from {}\u{001b}[0m", data.name(), client.display_name());
+            let notice = String::from("Whispers and channel messages are sent with /msg and /ch");
+            state.send_system_message(id, notice).await;
+        },
+        // only the server sends these, relaying them would let
+        // a client speak as the server or as another player
+        RustyCraftMessage::PlayerInit { .. }
+        | RustyCraftMessage::ConnectionData { .. }
+        | RustyCraftMessage::ChunkData { .. }
+        | RustyCraftMessage::Kick { .. }
+        | RustyCraftMessage::Hello { .. }
+        | RustyCraftMessage::Welcome { .. }
+        | RustyCraftMessage::PositionCorrection { .. }
+        | RustyCraftMessage::BlockRejected { .. }
+        | RustyCraftMessage::SystemMessage { .. }
+        | RustyCraftMessage::NameRejected { .. }
+        | RustyCraftMessage::ChatHistory { .. }
+        | RustyCraftMessage::SpawnPlayer { .. }
+        | RustyCraftMessage::DespawnPlayer
+        | RustyCraftMessage::UnloadChunks { .. }
+        | RustyCraftMessage::PlayerJoined { .. } => {
+            log!(Debug, "\u{001b}[31mDropped {} sent by {}\u{001b}[0m", data.name(), client.display_name());
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::rustycraft::chunk_utils::CHUNK_FORMAT_VERSION;
+
+use std::sync::Arc;
+
+use super::{codec::Framing, event::{EncodedEvent, serialize_event}, events::{RustyCraftMessage, ServerFeatures, WorldSummary}};
+
+// bump whenever RustyCraftMessage changes shape
+pub const PROTOCOL_VERSION: u32 = 11;
+// oldest Hello version the server still understands
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+// spoken by clients that never send Hello
+pub const LEGACY_PROTOCOL_VERSION: u32 = 0;
+
+// capability names a client may list in Hello
+pub const BINARY_FRAMING: &str = "binary_framing";
+
+// what the server tells clients about itself during the handshake
+#[derive(Clone)]
+pub struct ServerInfo {
+    pub world: WorldSummary
+}
+
+// checks a client's Hello and returns the Welcome to answer with and
+// the framing to switch to afterwards, or the reason it is rejected
+pub fn negotiate(info: &ServerInfo, protocol_version: u32, capabilities: &[String]) -> Result<(RustyCraftMessage, Framing), String> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+        return Err(format!(
+            "Client speaks protocol version {} but this server supports versions {} to {}",
+            protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ))
+    }
+
+    let binary_framing = capabilities.iter().any(|c| c == BINARY_FRAMING);
+    let features = ServerFeatures { 
+        compression: false, 
+        binary_framing, 
+        chunk_format_version: CHUNK_FORMAT_VERSION 
+    };
+    let framing = if binary_framing { Framing::Binary } else { Framing::Json };
+    let welcome = RustyCraftMessage::Welcome { protocol_version: PROTOCOL_VERSION, features, world: info.world.clone() };
+    Ok((welcome, framing))
+}
+
+// the event as a client speaking an older protocol version should get
+// it, rewritten into a message it understands where there is one
+pub fn for_version(event: Arc<EncodedEvent>, protocol_version: u32) -> Option<Arc<EncodedEvent>> {
+    let message = event.message();
+    if message.since_version() <= protocol_version {
+        return Some(event)
+    }
+    let older = match message {
+        RustyCraftMessage::SystemMessage { content } => RustyCraftMessage::ChatMessage { content: content.clone() },
+        RustyCraftMessage::Kick { reason } => RustyCraftMessage::ChatMessage { content: format!("Disconnected: {}", reason) },
+        RustyCraftMessage::NameRejected { name, reason } => RustyCraftMessage::ChatMessage { content: format!("Cannot join as {}: {}", name, reason) },
+        RustyCraftMessage::Whisper { from, to, content } => RustyCraftMessage::ChatMessage { content: format!("[{} -> {}] {}", from, to, content) },
+        RustyCraftMessage::ChannelMessage { from, channel, content } => RustyCraftMessage::ChatMessage { content: format!("[#{}] <{}> {}", channel, from, content) },
+        // undoes the client's edit just like another player's SetBlock would
+        RustyCraftMessage::BlockRejected { world_x, world_y, world_z, block, .. } => RustyCraftMessage::SetBlock { 
+            block: *block, 
+            world_x: *world_x, 
+            world_y: *world_y, 
+            world_z: *world_z 
+        },
+        RustyCraftMessage::SpawnPlayer { name, x, y, z, .. } => RustyCraftMessage::PlayerInit { name: name.clone(), x: *x, y: *y, z: *z },
+        RustyCraftMessage::DespawnPlayer => RustyCraftMessage::Disconnect,
+        // corrections, the chat backlog and unloads have no older form
+        _ => return None
+    };
+    Some(serialize_event(event.sender(), older))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::player_id::PlayerId;
+
+    fn sent_to(message: RustyCraftMessage, protocol_version: u32) -> Option<RustyCraftMessage> {
+        for_version(serialize_event(PlayerId(3), message), protocol_version).map(|event| {
+            assert_eq!(event.sender(), PlayerId(3));
+            event.message().clone()
+        })
+    }
+
+    #[test]
+    fn current_clients_get_everything() {
+        let unload = RustyCraftMessage::UnloadChunks { coords: vec![(1, 2)] };
+        assert!(matches!(sent_to(unload, PROTOCOL_VERSION), Some(RustyCraftMessage::UnloadChunks { .. })));
+    }
+
+    #[test]
+    fn messages_too_new_are_rewritten_or_skipped() {
+        let system = RustyCraftMessage::SystemMessage { content: String::from("hi") };
+        assert!(matches!(sent_to(system.clone(), 4), Some(RustyCraftMessage::SystemMessage { .. })));
+        assert!(matches!(sent_to(system, 3), Some(RustyCraftMessage::ChatMessage { content }) if content == "hi"));
+
+        let channel = RustyCraftMessage::ChannelMessage { from: String::from("Steve"), channel: String::from("staff"), content: String::from("hi") };
+        assert!(matches!(sent_to(channel, 6), Some(RustyCraftMessage::ChatMessage { content }) if content == "[#staff] <Steve> hi"));
+
+        let spawn = RustyCraftMessage::SpawnPlayer { name: String::from("Alex"), x: 1.0, y: 2.0, z: 3.0, yaw: 0.0, pitch: 0.0 };
+        assert!(matches!(sent_to(spawn, LEGACY_PROTOCOL_VERSION), Some(RustyCraftMessage::PlayerInit { .. })));
+        assert!(matches!(sent_to(RustyCraftMessage::DespawnPlayer, 8), Some(RustyCraftMessage::Disconnect)));
+
+        let correction = RustyCraftMessage::PositionCorrection { x: 0.0, y: 0.0, z: 0.0 };
+        assert!(sent_to(correction, 1).is_none());
+        assert!(sent_to(RustyCraftMessage::UnloadChunks { coords: Vec::new() }, 9).is_none());
+    }
+
+    #[test]
+    fn legacy_clients_only_get_legacy_messages() {
+        let messages = [
+            RustyCraftMessage::Kick { reason: String::from("bye") },
+            RustyCraftMessage::NameRejected { name: String::new(), reason: String::new() },
+            RustyCraftMessage::Whisper { from: String::new(), to: String::new(), content: String::new() },
+            RustyCraftMessage::ChannelMessage { from: String::new(), channel: String::new(), content: String::new() },
+            RustyCraftMessage::BlockRejected { world_x: 0, world_y: 0, world_z: 0, block: crate::rustycraft::block_type::BlockType::Air, reason: String::new() },
+            RustyCraftMessage::ChatHistory { lines: Vec::new() },
+            RustyCraftMessage::PlayerJoined { name: String::new() },
+        ];
+        for message in messages {
+            if let Some(sent) = sent_to(message, LEGACY_PROTOCOL_VERSION) {
+                assert_eq!(sent.since_version(), LEGACY_PROTOCOL_VERSION);
+            }
+        }
+    }
+}
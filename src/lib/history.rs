@@ -0,0 +1,218 @@
+use std::{collections::VecDeque, fs::{self, File, OpenOptions}, io::{self, Write}, path::PathBuf, time::{SystemTime, UNIX_EPOCH}};
+
+#[derive(Debug, Clone)]
+pub struct HistoryConfig {
+    // lines kept in memory for /history
+    pub size: usize,
+    // lines sent to players as they join
+    pub backlog: usize,
+    // size a chat log reaches before it is rotated
+    pub log_max_bytes: u64,
+    // rotated logs kept as chat.log.1, chat.log.2 and so on
+    pub log_files: usize
+}
+
+// recent public chat and server announcements, each line stamped with
+// the time, plus a log of everything said including whispers
+pub struct History {
+    config: HistoryConfig,
+    lines: VecDeque<String>,
+    // None once writing to it has failed
+    log: Option<ChatLog>
+}
+
+impl History {
+    pub fn new(config: HistoryConfig, log_path: PathBuf) -> History {
+        let log = match ChatLog::open(log_path.clone(), config.log_max_bytes, config.log_files) {
+            Ok(log) => Some(log),
+            Err(e) => {
+                log!(Warn, "\u{001b}[31mChat will not be logged, failed to open {}: {}\u{001b}[0m", log_path.display(), e);
+                None
+            }
+        };
+        History { lines: VecDeque::with_capacity(config.size), config, log }
+    }
+
+    // public lines go in the history shown to players, others are only logged
+    pub fn record(&mut self, text: &str, public: bool) {
+        let now = unix_time();
+        let line = format!("[{}] {}", clock_time(now), text);
+        if let Some(log) = self.log.as_mut() {
+            if let Err(e) = log.write(&format!("[{} {}] {}", date(now), clock_time(now), text)) {
+                log!(Warn, "\u{001b}[31mStopped logging chat: {}\u{001b}[0m", e);
+                self.log = None;
+            }
+        }
+        if public && self.config.size > 0 {
+            if self.lines.len() == self.config.size {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line);
+        }
+    }
+
+    // the lines sent to a player who just joined, oldest first
+    pub fn backlog(&self) -> Vec<String> {
+        self.page(1, self.config.backlog)
+    }
+
+    // page 1 is the latest page_size lines, page 2 the ones before them
+    // and so on, oldest first within a page
+    pub fn page(&self, page: usize, page_size: usize) -> Vec<String> {
+        let end = self.lines.len().saturating_sub((page.max(1) - 1) * page_size);
+        let start = end.saturating_sub(page_size);
+        self.lines.range(start..end).cloned().collect()
+    }
+
+    pub fn pages(&self, page_size: usize) -> usize {
+        self.lines.len().div_ceil(page_size)
+    }
+}
+
+struct ChatLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    files: usize
+}
+
+impl ChatLog {
+    fn open(path: PathBuf, max_bytes: u64, files: usize) -> io::Result<ChatLog> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(ChatLog { path, file, size, max_bytes, files })
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if self.size > 0 && self.size + length > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+        Ok(())
+    }
+
+    // shifts chat.log.1 to chat.log.2 and so on, dropping the oldest,
+    // then moves chat.log to chat.log.1 and starts a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+            name.push(format!(".{}", n));
+            self.path.with_file_name(name)
+        };
+        for n in (1..self.files).rev() {
+            let from = rotated(n);
+            if from.exists() {
+                fs::rename(from, rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// time of day in UTC
+pub fn clock_time(unix_time: u64) -> String {
+    let seconds = unix_time % 86400;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// calendar date in UTC, from Howard Hinnant's days-to-civil algorithm
+fn date(unix_time: u64) -> String {
+    let days = (unix_time / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory under the system temp dir, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("rustycraft-history-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn config(log_max_bytes: u64) -> HistoryConfig {
+        HistoryConfig { size: 5, backlog: 3, log_max_bytes, log_files: 2 }
+    }
+
+    // lines without their time stamps
+    fn texts(lines: Vec<String>) -> Vec<String> {
+        lines.into_iter().map(|line| line["[00:00:00] ".len()..].to_string()).collect()
+    }
+
+    #[test]
+    fn backlog_and_pages() {
+        let dir = TempDir::new("pages");
+        let mut history = History::new(config(1 << 20), dir.0.join("chat.log"));
+        for n in 1..=7 {
+            history.record(&n.to_string(), true);
+        }
+        history.record("whispered", false);
+
+        // only the latest size lines are kept
+        assert_eq!(texts(history.backlog()), ["5", "6", "7"]);
+        assert_eq!(history.pages(2), 3);
+        assert_eq!(texts(history.page(1, 2)), ["6", "7"]);
+        assert_eq!(texts(history.page(0, 2)), ["6", "7"]);
+        assert_eq!(texts(history.page(2, 2)), ["4", "5"]);
+        assert_eq!(texts(history.page(3, 2)), ["3"]);
+        assert!(history.page(4, 2).is_empty());
+    }
+
+    #[test]
+    fn logs_rotate_at_the_size_limit() {
+        let dir = TempDir::new("rotate");
+        // each logged line is 50 bytes with its date and newline,
+        // so every line after the first starts a new log
+        let mut history = History::new(config(90), dir.0.join("chat.log"));
+        for n in 1..=4 {
+            history.record(&format!("line {} padded out to length", n), n % 2 == 0);
+        }
+
+        let logged = |name: &str| fs::read_to_string(dir.0.join(name)).unwrap();
+        assert!(logged("chat.log").contains("line 4 "));
+        assert!(logged("chat.log.1").contains("line 3 "));
+        assert!(logged("chat.log.2").contains("line 2 "));
+        // only log_files rotated logs are kept
+        assert!(!dir.0.join("chat.log.3").exists());
+        assert_eq!(logged("chat.log").lines().count(), 1);
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(clock_time(951_782_400 + 3723), "01:02:03");
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::rustycraft::{chunk::CHUNK_SIZE, coord_map::CoordMap};
+
+use super::player_id::PlayerId;
+
+// chunk holding the block at a position, blocks being centred on integer coordinates
+pub fn chunk_of(x: f32, z: f32) -> (i32, i32) {
+    ((x.round() as i32).div_euclid(CHUNK_SIZE as i32), (z.round() as i32).div_euclid(CHUNK_SIZE as i32))
+}
+
+// which chunk every joined player is in, for finding who is near something
+pub struct InterestGrid {
+    cells: CoordMap<HashSet<PlayerId>>,
+    chunks: HashMap<PlayerId, (i32, i32)>
+}
+
+impl InterestGrid {
+    pub fn new() -> InterestGrid {
+        InterestGrid { cells: CoordMap::new(), chunks: HashMap::new() }
+    }
+
+    // returns whether the player changed chunk, or was not in the grid yet
+    pub fn update(&mut self, id: PlayerId, chunk: (i32, i32)) -> bool {
+        match self.chunks.insert(id, chunk) {
+            Some(old) if old == chunk => return false,
+            Some(old) => self.remove_from_cell(id, old),
+            None => {}
+        }
+        match self.cells.get_mut(chunk.0, chunk.1) {
+            Some(cell) => {
+                cell.insert(id);
+            },
+            None => {
+                let mut cell = HashSet::new();
+                cell.insert(id);
+                self.cells.insert(chunk.0, chunk.1, cell);
+            }
+        }
+        true
+    }
+
+    pub fn remove(&mut self, id: PlayerId) {
+        if let Some(chunk) = self.chunks.remove(&id) {
+            self.remove_from_cell(id, chunk);
+        }
+    }
+
+    fn remove_from_cell(&mut self, id: PlayerId, (x, z): (i32, i32)) {
+        if let Some(cell) = self.cells.get_mut(x, z) {
+            cell.remove(&id);
+            if cell.is_empty() {
+                self.cells.remove(x, z);
+            }
+        }
+    }
+
+    // players within distance chunks of a chunk, on both axes
+    pub fn near(&self, (x, z): (i32, i32), distance: i32) -> Vec<PlayerId> {
+        let mut players = Vec::new();
+        for cell_x in x - distance..=x + distance {
+            for cell_z in z - distance..=z + distance {
+                if let Some(cell) = self.cells.get(cell_x, cell_z) {
+                    players.extend(cell.iter().copied());
+                }
+            }
+        }
+        players
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(grid: &InterestGrid, chunk: (i32, i32), distance: i32) -> Vec<PlayerId> {
+        let mut players = grid.near(chunk, distance);
+        players.sort_by_key(|id| id.0);
+        players
+    }
+
+    #[test]
+    fn entering_and_leaving_the_radius() {
+        let mut grid = InterestGrid::new();
+        assert!(grid.update(PlayerId(1), (0, 0)));
+        assert!(grid.update(PlayerId(2), (5, 0)));
+        assert_eq!(near(&grid, (0, 0), 2), [PlayerId(1)]);
+
+        // coming within range on one axis is not enough
+        assert!(grid.update(PlayerId(2), (2, 3)));
+        assert_eq!(near(&grid, (0, 0), 2), [PlayerId(1)]);
+        assert!(grid.update(PlayerId(2), (2, -2)));
+        assert_eq!(near(&grid, (0, 0), 2), [PlayerId(1), PlayerId(2)]);
+
+        // staying in the same chunk is not a change
+        assert!(!grid.update(PlayerId(2), (2, -2)));
+
+        assert!(grid.update(PlayerId(2), (-3, 0)));
+        assert_eq!(near(&grid, (0, 0), 2), [PlayerId(1)]);
+        assert_eq!(near(&grid, (-1, 0), 2), [PlayerId(1), PlayerId(2)]);
+    }
+
+    #[test]
+    fn removed_players_are_not_near() {
+        let mut grid = InterestGrid::new();
+        grid.update(PlayerId(1), (0, 0));
+        grid.update(PlayerId(2), (0, 0));
+        grid.remove(PlayerId(1));
+        assert_eq!(near(&grid, (0, 0), 0), [PlayerId(2)]);
+        grid.remove(PlayerId(2));
+        assert!(grid.near((0, 0), 0).is_empty());
+        // and come back as new
+        assert!(grid.update(PlayerId(1), (0, 0)));
+    }
+
+    #[test]
+    fn chunks_of_positions() {
+        assert_eq!(chunk_of(0.0, 0.0), (0, 0));
+        assert_eq!(chunk_of(15.4, 15.6), (0, 1));
+        assert_eq!(chunk_of(-0.4, -0.6), (0, -1));
+        assert_eq!(chunk_of(-16.6, 32.0), (-2, 2));
+    }
+}
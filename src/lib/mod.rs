@@ -1,7 +1,27 @@
 // server-exclusive structures
+#[macro_use]
+pub mod console;
 pub mod direction;
 pub mod events;
 pub mod event;
+pub mod player_id;
+pub mod codec;
 pub mod client;
 pub mod clients;
-pub mod state;
\ No newline at end of file
+pub mod connection;
+pub mod game;
+pub mod handshake;
+pub mod interest;
+pub mod streaming;
+pub mod movement;
+pub mod placement;
+pub mod names;
+pub mod state;
+pub mod ops;
+pub mod bans;
+pub mod whitelist;
+pub mod commands;
+pub mod chat;
+pub mod history;
+pub mod config;
+pub mod admission;
@@ -0,0 +1,192 @@
+use std::{fmt, time::Duration};
+
+use crate::rustycraft::block_type::BlockType;
+
+// distance between collision samples along a move, in blocks
+const COLLISION_STEP: f32 = 0.25;
+
+// flying is not checked for: nothing stops a player climbing through the air
+// at max_rise_speed or hovering, as the server has no physics to tell a jump
+// or a fall apart from a client that never comes down
+#[derive(Debug, Copy, Clone)]
+pub struct MovementConfig {
+    // speed and collision checks, positions are always checked to be finite
+    pub enabled: bool,
+    // blocks per second
+    pub max_horizontal_speed: f32,
+    pub max_rise_speed: f32,
+    pub max_fall_speed: f32,
+    // extra distance allowed on every move to absorb network jitter
+    pub tolerance: f32,
+    // time since the last accepted move counted towards the allowance at most,
+    // so standing still does not bank a long distance
+    pub max_elapsed: Duration
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        MovementConfig {
+            enabled: true,
+            max_horizontal_speed: 12.0,
+            max_rise_speed: 12.0,
+            max_fall_speed: 80.0,
+            tolerance: 2.0,
+            max_elapsed: Duration::from_secs(1)
+        }
+    }
+}
+
+pub enum Violation {
+    NotFinite,
+    TooFast { distance: f32, allowed: f32 },
+    TooFastVertical { distance: f32, allowed: f32 },
+    Collision { x: i32, y: i32, z: i32, block: BlockType }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::NotFinite => write!(f, "moved to a position that is not a finite number"),
+            Violation::TooFast { distance, allowed } => 
+                write!(f, "moved {:.2} blocks horizontally, {:.2} allowed", distance, allowed),
+            Violation::TooFastVertical { distance, allowed } => 
+                write!(f, "moved {:.2} blocks vertically, {:.2} allowed", distance, allowed),
+            Violation::Collision { x, y, z, block } => 
+                write!(f, "moved through {:?} at {}, {}, {}", block, x, y, z)
+        }
+    }
+}
+
+// checks a move between two positions made over elapsed time, with
+// blocks looking up the world and returning None for unloaded chunks;
+// blocks are taken to be centred on integer coordinates
+pub fn check_move(blocks: impl Fn(i32, i32, i32) -> Option<BlockType>, config: &MovementConfig, from: (f32, f32, f32), to: (f32, f32, f32), elapsed: Duration) -> Result<(), Violation> {
+    // NaN would pass every comparison below
+    if !(to.0.is_finite() && to.1.is_finite() && to.2.is_finite()) {
+        return Err(Violation::NotFinite)
+    }
+    if !config.enabled {
+        return Ok(())
+    }
+
+    let seconds = elapsed.min(config.max_elapsed).as_secs_f32();
+    let (dx, dy, dz) = (to.0 - from.0, to.1 - from.1, to.2 - from.2);
+
+    let horizontal = (dx * dx + dz * dz).sqrt();
+    let allowed = config.max_horizontal_speed * seconds + config.tolerance;
+    if horizontal > allowed {
+        return Err(Violation::TooFast { distance: horizontal, allowed })
+    }
+
+    let speed = if dy > 0.0 { config.max_rise_speed } else { config.max_fall_speed };
+    let allowed = speed * seconds + config.tolerance;
+    if dy.abs() > allowed {
+        return Err(Violation::TooFastVertical { distance: dy.abs(), allowed })
+    }
+
+    // sample the path, ignoring the block the move started in so
+    // a player the server thinks is already inside one can get out
+    let start = block_at(from);
+    let steps = ((horizontal * horizontal + dy * dy).sqrt() / COLLISION_STEP).ceil() as usize;
+    for step in 1..=steps {
+        let t = step as f32 / steps as f32;
+        let cell = block_at((from.0 + dx * t, from.1 + dy * t, from.2 + dz * t));
+        if cell == start {
+            continue;
+        }
+        // unloaded chunks cannot be checked
+        if let Some(block) = blocks(cell.0, cell.1, cell.2) {
+            if block.is_solid() {
+                return Err(Violation::Collision { x: cell.0, y: cell.1, z: cell.2, block })
+            }
+        }
+    }
+    Ok(())
+}
+
+fn block_at(position: (f32, f32, f32)) -> (i32, i32, i32) {
+    (position.0.round() as i32, position.1.round() as i32, position.2.round() as i32)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // flat ground: stone at y 0 and below, air above
+    fn ground(_: i32, y: i32, _: i32) -> Option<BlockType> {
+        Some(if y <= 0 { BlockType::Stone } else { BlockType::Air })
+    }
+
+    fn check(from: (f32, f32, f32), to: (f32, f32, f32), elapsed: Duration) -> Result<(), Violation> {
+        check_move(ground, &MovementConfig::default(), from, to, elapsed)
+    }
+
+    #[test]
+    fn walking_is_allowed() {
+        assert!(check((0.0, 2.0, 0.0), (5.0, 2.0, 0.0), Duration::from_millis(500)).is_ok());
+        assert!(check((0.0, 2.0, 0.0), (0.0, 2.0, 0.0), Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn horizontal_speed() {
+        // 12 blocks a second plus 2 of tolerance
+        assert!(check((0.0, 2.0, 0.0), (7.9, 2.0, 0.0), Duration::from_millis(500)).is_ok());
+        assert!(matches!(
+            check((0.0, 2.0, 0.0), (6.0, 2.0, 6.0), Duration::from_millis(500)), 
+            Err(Violation::TooFast { .. })
+        ));
+        // waiting longer than max_elapsed does not bank distance
+        assert!(matches!(
+            check((0.0, 2.0, 0.0), (30.0, 2.0, 0.0), Duration::from_secs(10)), 
+            Err(Violation::TooFast { .. })
+        ));
+    }
+
+    #[test]
+    fn falling_is_faster_than_rising() {
+        assert!(check((0.0, 50.0, 0.0), (0.0, 20.0, 0.0), Duration::from_millis(500)).is_ok());
+        assert!(matches!(
+            check((0.0, 20.0, 0.0), (0.0, 50.0, 0.0), Duration::from_millis(500)), 
+            Err(Violation::TooFastVertical { .. })
+        ));
+        assert!(matches!(
+            check((0.0, 100.0, 0.0), (0.0, 10.0, 0.0), Duration::from_millis(500)), 
+            Err(Violation::TooFastVertical { .. })
+        ));
+    }
+
+    #[test]
+    fn solid_blocks_stop_moves() {
+        assert!(matches!(
+            check((0.0, 2.0, 0.0), (0.0, -1.0, 0.0), Duration::from_millis(500)), 
+            Err(Violation::Collision { y: 0, block: BlockType::Stone, .. })
+        ));
+        // a wall between the two ends is caught even though both are clear
+        let wall = |x: i32, y: i32, _: i32| Some(if x == 2 || y <= 0 { BlockType::Stone } else { BlockType::Air });
+        let checked = check_move(wall, &MovementConfig::default(), (0.0, 2.0, 0.0), (4.0, 2.0, 0.0), Duration::from_millis(500));
+        assert!(matches!(checked, Err(Violation::Collision { x: 2, .. })));
+    }
+
+    #[test]
+    fn players_stuck_in_a_block_can_get_out() {
+        assert!(check((0.0, 0.0, 0.0), (0.0, 1.0, 0.0), Duration::from_millis(500)).is_ok());
+    }
+
+    #[test]
+    fn unloaded_chunks_are_not_checked() {
+        let checked = check_move(|_, _, _| None, &MovementConfig::default(), (0.0, 2.0, 0.0), (0.0, -3.0, 0.0), Duration::from_millis(500));
+        assert!(checked.is_ok());
+    }
+
+    #[test]
+    fn non_finite_positions_are_always_rejected() {
+        let disabled = MovementConfig { enabled: false, ..MovementConfig::default() };
+        for to in [(f32::NAN, 2.0, 0.0), (0.0, f32::INFINITY, 0.0), (0.0, 2.0, f32::NEG_INFINITY)] {
+            assert!(matches!(check((0.0, 2.0, 0.0), to, Duration::from_millis(500)), Err(Violation::NotFinite)));
+            assert!(matches!(check_move(ground, &disabled, (0.0, 2.0, 0.0), to, Duration::ZERO), Err(Violation::NotFinite)));
+        }
+        // anything else goes when the checks are off
+        assert!(check_move(ground, &disabled, (0.0, 2.0, 0.0), (500.0, -10.0, 0.0), Duration::ZERO).is_ok());
+    }
+}
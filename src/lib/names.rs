@@ -0,0 +1,95 @@
+use std::fmt;
+
+pub const DEFAULT_NAME_CHARACTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
+
+#[derive(Debug, Clone)]
+pub struct NameConfig {
+    // in characters, not bytes
+    pub max_length: usize,
+    // every character a name may use
+    pub allowed_characters: String
+}
+
+pub enum NameError {
+    AlreadyJoined,
+    Empty,
+    SurroundingWhitespace,
+    TooLong { max_length: usize },
+    InvalidCharacter { character: char },
+    Taken
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::AlreadyJoined => write!(f, "You have already joined"),
+            NameError::Empty => write!(f, "Name cannot be empty"),
+            NameError::SurroundingWhitespace => write!(f, "Name cannot start or end with whitespace"),
+            NameError::TooLong { max_length } => write!(f, "Name cannot be longer than {} characters", max_length),
+            // debug formatting escapes control characters
+            NameError::InvalidCharacter { character } => write!(f, "Name cannot contain {:?}", character),
+            NameError::Taken => write!(f, "Someone with that name is already online")
+        }
+    }
+}
+
+// checks a name asked for in PlayerJoin against the rules and the
+// names of everyone online, which are compared ignoring case
+pub fn check_name<'a>(config: &NameConfig, name: &str, mut online: impl Iterator<Item = &'a str>) -> Result<(), NameError> {
+    if name.trim().is_empty() {
+        return Err(NameError::Empty)
+    }
+    if name.trim() != name {
+        return Err(NameError::SurroundingWhitespace)
+    }
+    if name.chars().count() > config.max_length {
+        return Err(NameError::TooLong { max_length: config.max_length })
+    }
+    if let Some(character) = name.chars().find(|c| !config.allowed_characters.contains(*c)) {
+        return Err(NameError::InvalidCharacter { character })
+    }
+    if online.any(|other| other.to_lowercase() == name.to_lowercase()) {
+        return Err(NameError::Taken)
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str, online: &[&str]) -> Result<(), NameError> {
+        let config = NameConfig { max_length: 16, allowed_characters: DEFAULT_NAME_CHARACTERS.to_string() };
+        check_name(&config, name, online.iter().copied())
+    }
+
+    #[test]
+    fn valid_names() {
+        assert!(check("steve", &[]).is_ok());
+        assert!(check("Player_01", &["steve", "alex"]).is_ok());
+        assert!(check("sixteen_chars_ok", &[]).is_ok());
+    }
+
+    #[test]
+    fn empty_names() {
+        assert!(matches!(check("", &[]), Err(NameError::Empty)));
+        assert!(matches!(check("   ", &[]), Err(NameError::Empty)));
+        assert!(matches!(check(" steve", &[]), Err(NameError::SurroundingWhitespace)));
+    }
+
+    #[test]
+    fn bad_characters() {
+        assert!(matches!(check("ste ve", &[]), Err(NameError::InvalidCharacter { character: ' ' })));
+        assert!(matches!(check("steve\u{1b}[31m", &[]), Err(NameError::InvalidCharacter { character: '\u{1b}' })));
+        assert!(matches!(check("stéve", &[]), Err(NameError::InvalidCharacter { character: 'é' })));
+        assert!(matches!(check("seventeen_chars_x", &[]), Err(NameError::TooLong { max_length: 16 })));
+    }
+
+    #[test]
+    fn duplicates_ignore_case() {
+        assert!(matches!(check("steve", &["alex", "steve"]), Err(NameError::Taken)));
+        assert!(matches!(check("STEVE", &["Steve"]), Err(NameError::Taken)));
+        assert!(check("steve2", &["steve"]).is_ok());
+    }
+}
@@ -0,0 +1,81 @@
+use std::{collections::HashMap, io, path::PathBuf};
+
+use crate::rustycraft::files::JsonFile;
+
+// permission levels, each allowed everything the ones below it are
+pub const LEVEL_PLAYER: u8 = 0;
+pub const LEVEL_MODERATOR: u8 = 1;
+pub const LEVEL_ADMIN: u8 = 2;
+pub const LEVEL_OWNER: u8 = 3;
+
+// permission level of every operator by name, kept in
+// ops.json; everyone else is at LEVEL_PLAYER
+pub struct Ops {
+    file: JsonFile<HashMap<String, u8>>
+}
+
+impl Ops {
+    pub fn load(path: PathBuf) -> io::Result<Ops> {
+        Ok(Ops { file: JsonFile::load(path)? })
+    }
+
+    // names match ignoring case, as they do when joining; should a hand
+    // edited file list one name twice, the higher level counts
+    pub fn level(&self, name: &str) -> u8 {
+        let name = name.to_lowercase();
+        self.file.value.iter()
+            .filter(|(op, _)| op.to_lowercase() == name)
+            .map(|(_, level)| *level)
+            .max()
+            .unwrap_or(LEVEL_PLAYER)
+    }
+
+    pub fn set(&mut self, name: &str, level: u8) -> io::Result<()> {
+        self.file.value.retain(|op, _| op.to_lowercase() != name.to_lowercase());
+        self.file.value.insert(name.to_string(), level);
+        self.file.save()
+    }
+
+    // returns whether name was an operator
+    pub fn remove(&mut self, name: &str) -> io::Result<bool> {
+        let count = self.file.value.len();
+        self.file.value.retain(|op, _| op.to_lowercase() != name.to_lowercase());
+        if self.file.value.len() == count {
+            return Ok(false)
+        }
+        self.file.save()?;
+        Ok(true)
+    }
+
+    pub fn file(&mut self) -> &mut JsonFile<HashMap<String, u8>> {
+        &mut self.file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn names_match_ignoring_case() {
+        let path = env::temp_dir().join(format!("rustycraft-ops-{}.json", process::id()));
+        fs::write(&path, r#"{"Admin": 3, "MOD": 1, "mod": 2}"#).unwrap();
+        let mut ops = Ops::load(path.clone()).unwrap();
+        assert_eq!(ops.level("admin"), LEVEL_OWNER);
+        assert_eq!(ops.level("ADMIN"), LEVEL_OWNER);
+        assert_eq!(ops.level("Mod"), LEVEL_ADMIN);
+        assert_eq!(ops.level("nobody"), LEVEL_PLAYER);
+
+        // replaces every spelling rather than adding another
+        ops.set("mOd", LEVEL_MODERATOR).unwrap();
+        assert_eq!(ops.level("mod"), LEVEL_MODERATOR);
+        assert_eq!(ops.file().value.len(), 2);
+
+        assert!(ops.remove("admin").unwrap());
+        assert!(!ops.remove("admin").unwrap());
+        assert_eq!(ops.level("Admin"), LEVEL_PLAYER);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+use crate::rustycraft::{block_type::BlockType, chunk::CHUNK_HEIGHT};
+
+// player bounding box around the reported position, which is at eye level
+const PLAYER_HALF_WIDTH: f32 = 0.3;
+const PLAYER_EYE_HEIGHT: f32 = 1.5;
+const PLAYER_HEAD_ROOM: f32 = 0.3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct PlacementConfig {
+    // furthest a player can edit a block from, in blocks
+    pub max_reach: f32
+}
+
+impl Default for PlacementConfig {
+    fn default() -> Self {
+        PlacementConfig { max_reach: 8.0 }
+    }
+}
+
+pub enum PlacementError {
+    OutOfBounds,
+    OutOfReach { distance: f32 },
+    InsidePlayer { name: String }
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlacementError::OutOfBounds => write!(f, "Blocks can only be placed from y 0 to {}", CHUNK_HEIGHT - 1),
+            PlacementError::OutOfReach { distance } => write!(f, "Block is {:.1} blocks away, too far to reach", distance),
+            PlacementError::InsidePlayer { name } => write!(f, "Block would be inside {}", name)
+        }
+    }
+}
+
+// checks an edit of the block at x, y, z by a player at position, with others
+// being the names and positions of every other player; blocks are
+// taken to be centred on integer coordinates
+pub fn check_placement(
+    config: &PlacementConfig, 
+    position: (f32, f32, f32), 
+    others: impl Iterator<Item = (String, (f32, f32, f32))>,
+    (x, y, z): (i32, i32, i32), 
+    block: BlockType
+) -> Result<(), PlacementError> {
+    if y < 0 || y >= CHUNK_HEIGHT as i32 {
+        return Err(PlacementError::OutOfBounds)
+    }
+
+    let (dx, dy, dz) = (x as f32 - position.0, y as f32 - position.1, z as f32 - position.2);
+    let distance = (dx * dx + dy * dy + dz * dz).sqrt();
+    if distance > config.max_reach {
+        return Err(PlacementError::OutOfReach { distance })
+    }
+
+    // breaking blocks never traps anyone
+    if !block.is_solid() {
+        return Ok(())
+    }
+    for (name, (px, py, pz)) in others {
+        let overlaps = (x as f32 - px).abs() < 0.5 + PLAYER_HALF_WIDTH
+            && (z as f32 - pz).abs() < 0.5 + PLAYER_HALF_WIDTH
+            && y as f32 + 0.5 > py - PLAYER_EYE_HEIGHT
+            && (y as f32) - 0.5 < py + PLAYER_HEAD_ROOM;
+        if overlaps {
+            return Err(PlacementError::InsidePlayer { name })
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(position: (f32, f32, f32), others: Vec<(&str, (f32, f32, f32))>, coords: (i32, i32, i32), block: BlockType) -> Result<(), PlacementError> {
+        let others = others.into_iter().map(|(name, position)| (name.to_string(), position));
+        check_placement(&PlacementConfig::default(), position, others, coords, block)
+    }
+
+    #[test]
+    fn within_reach() {
+        assert!(check((0.0, 10.0, 0.0), vec![], (3, 8, 3), BlockType::Stone).is_ok());
+        assert!(check((0.0, 10.0, 0.0), vec![], (8, 10, 0), BlockType::Stone).is_ok());
+        assert!(matches!(
+            check((0.0, 10.0, 0.0), vec![], (6, 10, 6), BlockType::Stone), 
+            Err(PlacementError::OutOfReach { .. })
+        ));
+        // breaking is held to the same reach
+        assert!(matches!(
+            check((0.0, 10.0, 0.0), vec![], (0, 10, 9), BlockType::Air), 
+            Err(PlacementError::OutOfReach { .. })
+        ));
+    }
+
+    #[test]
+    fn within_world_height() {
+        assert!(matches!(check((0.0, 1.0, 0.0), vec![], (0, -1, 0), BlockType::Stone), Err(PlacementError::OutOfBounds)));
+        let top = CHUNK_HEIGHT as i32;
+        let position = (0.0, top as f32, 0.0);
+        assert!(matches!(check(position, vec![], (0, top, 0), BlockType::Stone), Err(PlacementError::OutOfBounds)));
+        assert!(check(position, vec![], (0, top - 1, 1), BlockType::Stone).is_ok());
+        assert!(check((0.0, 1.0, 0.0), vec![], (0, 0, 1), BlockType::Stone).is_ok());
+    }
+
+    #[test]
+    fn not_inside_players() {
+        let other = vec![("alex", (2.0, 11.5, 0.0))];
+        // from the block at their feet up to the one their head pokes into
+        for y in [10, 11, 12] {
+            assert!(matches!(
+                check((0.0, 11.5, 0.0), other.clone(), (2, y, 0), BlockType::Stone), 
+                Err(PlacementError::InsidePlayer { ref name }) if name == "alex"
+            ));
+        }
+        // below their feet, above their head and beside them
+        assert!(check((0.0, 11.5, 0.0), other.clone(), (2, 9, 0), BlockType::Stone).is_ok());
+        assert!(check((0.0, 11.5, 0.0), other.clone(), (2, 13, 0), BlockType::Stone).is_ok());
+        assert!(check((0.0, 11.5, 0.0), other.clone(), (3, 11, 0), BlockType::Stone).is_ok());
+        // clearing a block around someone is always fine
+        assert!(check((0.0, 11.5, 0.0), other, (2, 11, 0), BlockType::Air).is_ok());
+    }
+}
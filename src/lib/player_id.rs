@@ -0,0 +1,46 @@
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+// compact per-session player identifier; JSON clients see it
+// as a string, binary clients as a plain u32
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PlayerId(pub u32);
+
+impl PlayerId {
+    // sender of server-originated events, an empty string in JSON
+    pub const SERVER: PlayerId = PlayerId(0);
+}
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for PlayerId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_u32(self.0)
+        }
+
+        if *self == PlayerId::SERVER {
+            serializer.serialize_str("")
+        } else {
+            serializer.serialize_str(self.0.to_string().as_str())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PlayerId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return u32::deserialize(deserializer).map(PlayerId)
+        }
+
+        let id = String::deserialize(deserializer)?;
+        if id.is_empty() {
+            return Ok(PlayerId::SERVER)
+        }
+        id.parse::<u32>().map(PlayerId).map_err(D::Error::custom)
+    }
+}
@@ -1,28 +1,368 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}};
+use std::{collections::{HashMap, HashSet}, io, net::IpAddr, path::PathBuf, sync::Arc, time::{SystemTime, UNIX_EPOCH}};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::{sync::Notify, task::block_in_place, time::Instant};
 
-use crate::rustycraft::world::World;
+use crate::rustycraft::{files::JsonFile, player_data::PlayerData, world::World};
 
-use super::clients::Clients;
+use super::{admission::Admission, bans::BanList, chat::ChatConfig, history::History, interest::{InterestGrid, chunk_of}, client::Client, clients::Clients, config::ServerConfig, event::{EncodedEvent, serialize_event}, events::RustyCraftMessage, ops::{LEVEL_PLAYER, Ops}, player_id::PlayerId, streaming::CHUNKS_PER_MESSAGE, whitelist::Whitelist};
 
-// struct for organizing server state
-// for each read thread
-#[derive(Clone)]
+// server state, owned by the game task
 pub struct State {
-    pub world: Arc<Mutex<World>>,
-    pub names: Arc<Mutex<HashMap<String, String>>>,
-    pub clients: Clients
+    pub world: World,
+    pub clients: Clients,
+    pub ops: Ops,
+    pub bans: BanList,
+    pub ip_bans: BanList,
+    pub whitelist: Whitelist,
+    pub mutes: BanList,
+    pub chat: ChatConfig,
+    pub history: History,
+    pub interest: InterestGrid,
+    // in chunks
+    pub view_distance: i32,
+    pub started: Instant,
+    // shared with the accept loop
+    pub admission: Arc<Admission>,
+    // signalled to have the server save and shut down
+    pub stop: Arc<Notify>
 }
 
 impl State {
-    pub fn new() -> State {
-        let mut world = World::new("world");
-        // pre-load spawn chunk
-        // for getting spawn y
-        world.get_or_insert_chunk(0, 0);
-        State {
-            world: Arc::new(Mutex::new(world)),
-            names: Arc::new(Mutex::new(HashMap::new())),
-            clients: Clients::new()
-        }
-    }
-}
\ No newline at end of file
+    pub fn new(config: &ServerConfig) -> State {
+        let mut world = World::new(&config.world, config.seed);
+        world.terrain_amplitude = config.terrain_amplitude;
+        world.max_loaded_chunks = config.max_loaded_chunks;
+        let dir = format!("worlds/{}", world.name);
+        let ops = load_list(Ops::load, &dir, "ops.json");
+        let bans = load_list(BanList::load, &dir, "bans.json");
+        let ip_bans = load_list(BanList::load, &dir, "ip-bans.json");
+        let whitelist = load_list(Whitelist::load, &dir, "whitelist.json");
+        let mutes = load_list(BanList::load, &dir, "mutes.json");
+        let state = State {
+            world,
+            clients: Clients::new(),
+            ops,
+            bans,
+            ip_bans,
+            whitelist,
+            mutes,
+            chat: config.chat(),
+            history: History::new(config.history(), format!("{}/chat.log", dir).into()),
+            interest: InterestGrid::new(),
+            view_distance: config.view_distance as i32,
+            started: Instant::now(),
+            admission: Arc::new(Admission::new(config.max_players, config.max_connections_per_ip)),
+            stop: Arc::new(Notify::new())
+        };
+        state.sync_ip_bans();
+        state
+    }
+
+    pub fn permission_level(&self, id: PlayerId) -> u8 {
+        match self.clients.get(id).and_then(|c| c.name.as_deref()) {
+            Some(name) => self.ops.level(name),
+            None => LEVEL_PLAYER
+        }
+    }
+
+    // why a player with this name and address may not join, if they may not
+    pub fn refusal(&self, name: &str, address: IpAddr) -> Option<String> {
+        if let Some(ban) = self.bans.get(name) {
+            return Some(format!("You are banned: {}", ban.reason))
+        }
+        if let Some(ban) = self.ip_bans.get(&address.to_string()) {
+            return Some(format!("Your address is banned: {}", ban.reason))
+        }
+        // operators can always join, so turning the whitelist on never locks them out
+        if !self.whitelist.allows(name) && self.ops.level(name) == LEVEL_PLAYER {
+            return Some(String::from("You are not whitelisted on this server"))
+        }
+        None
+    }
+
+    // hands the ip bans to the accept loop
+    pub fn sync_ip_bans(&self) {
+        let banned = self.ip_bans.bans()
+            .filter_map(|(address, ban)| Some((address.parse::<IpAddr>().ok()?, ban.reason.clone())))
+            .collect();
+        self.admission.set_banned(banned);
+    }
+
+    // kicks every joined player the bans or whitelist no longer let in
+    pub fn enforce_lists(&self) {
+        for client in self.clients.clients() {
+            let name = match &client.name {
+                Some(name) => name,
+                None => continue
+            };
+            if let Some(reason) = self.refusal(name, client.address) {
+                log!(Info, "\u{001b}[33mKicking {}: {}\u{001b}[0m", name, reason);
+                client.kick(&reason);
+            }
+        }
+    }
+
+    // reads the ops, bans, whitelist and mutes files again, either all of them or
+    // only those edited since they were last read; returns why any failed,
+    // in which case their old contents are kept
+    pub fn reload_lists(&mut self, only_changed: bool) -> Vec<String> {
+        let results = [
+            reload_list(self.ops.file(), only_changed),
+            reload_list(self.bans.file(), only_changed),
+            reload_list(self.ip_bans.file(), only_changed),
+            reload_list(self.whitelist.file(), only_changed),
+            reload_list(self.mutes.file(), only_changed)
+        ];
+        let mut errors = Vec::new();
+        let mut reloaded = false;
+        for result in results {
+            match result {
+                Ok(changed) => reloaded |= changed,
+                Err(e) => errors.push(e)
+            }
+        }
+        if reloaded {
+            self.sync_ip_bans();
+            self.enforce_lists();
+        }
+        errors
+    }
+
+    // saves players and dirty chunks, returning how many chunks were written;
+    // chunk serialization and region writes are blocking
+    pub fn save(&mut self) -> usize {
+        self.remember_players();
+        block_in_place(|| self.world.save())
+    }
+
+    pub async fn broadcast(&mut self, message: &RustyCraftMessage, sender_id: PlayerId) {
+        let failed = self.clients.broadcast(message, sender_id).await;
+        self.drop_clients(failed).await;
+    }
+
+    // sends a player's movement to everyone who can see them
+    pub async fn send_to_viewers(&mut self, id: PlayerId, message: RustyCraftMessage) {
+        let viewers: Vec<PlayerId> = match self.clients.get(id) {
+            Some(client) => client.visible.iter().copied().collect(),
+            None => return
+        };
+        self.send_to(&viewers, message, id).await;
+    }
+
+    // sends a change to a chunk to every joined player that has it; anyone
+    // else holding it will be sent the whole chunk again instead, so
+    // nobody is left with stale terrain
+    pub async fn broadcast_to_chunk(&mut self, chunk: (i32, i32), message: RustyCraftMessage, sender_id: PlayerId) {
+        let mut holders = Vec::new();
+        for client in self.clients.clients_mut().filter(|c| c.chunks.has(chunk)) {
+            match client.name {
+                Some(_) => holders.push(client.id),
+                None => client.chunks.forget(chunk)
+            }
+        }
+        self.send_to(&holders, message, sender_id).await;
+    }
+
+    // call once a joined player has moved; if they changed chunk, players
+    // coming into or going out of view distance are spawned or despawned
+    // for them, and they for those players
+    pub async fn update_interest(&mut self, id: PlayerId) {
+        let (chunk, visible) = match self.clients.get(id) {
+            Some(client) if client.name.is_some() => (chunk_of(client.x, client.z), client.visible.clone()),
+            _ => return
+        };
+        // everyone else who moved into or out of range updated themselves
+        if !self.interest.update(id, chunk) {
+            return
+        }
+        let in_range: HashSet<PlayerId> = self.interest.near(chunk, self.view_distance)
+            .into_iter()
+            .filter(|other| *other != id)
+            .collect();
+        let entered: Vec<PlayerId> = in_range.difference(&visible).copied().collect();
+        let left: Vec<PlayerId> = visible.difference(&in_range).copied().collect();
+        for other in entered {
+            self.set_visible(id, other, true).await;
+        }
+        for other in left {
+            self.set_visible(id, other, false).await;
+        }
+    }
+
+    // sends every joined player up to max of the chunks around them they
+    // do not have yet, and unloads the ones they have moved away from
+    pub async fn stream_chunks(&mut self, max: usize) {
+        let view_distance = self.view_distance;
+        let mut batches = Vec::new();
+        for client in self.clients.clients_mut() {
+            if client.name.is_none() {
+                continue
+            }
+            let unloaded = client.chunks.recenter(chunk_of(client.x, client.z), view_distance);
+            let batch = client.chunks.next_batch(max);
+            if !unloaded.is_empty() || !batch.is_empty() {
+                batches.push((client.id, unloaded, batch));
+            }
+        }
+
+        // players near each other are mostly sent the same chunks
+        let mut serialized = HashMap::new();
+        for (id, unloaded, batch) in batches {
+            if !unloaded.is_empty() {
+                let message = RustyCraftMessage::UnloadChunks { coords: unloaded };
+                self.send_or_drop(id, serialize_event(PlayerId::SERVER, message)).await;
+            }
+            self.send_chunks(id, &batch, &mut serialized).await;
+        }
+        block_in_place(|| self.world.unload_excess_chunks());
+    }
+
+    // sends chunks to a client in ChunkData messages of at most CHUNKS_PER_MESSAGE,
+    // serializing each at most once for everything sent with the same cache
+    pub async fn send_chunks(&mut self, id: PlayerId, coords: &[(i32, i32)], serialized: &mut HashMap<(i32, i32), String>) {
+        for batch in coords.chunks(CHUNKS_PER_MESSAGE) {
+            let world = &mut self.world;
+            // chunk generation and disk reads are blocking
+            let chunks = block_in_place(|| {
+                batch.iter()
+                    .map(|(x, z)| {
+                        let data = serialized.entry((*x, *z))
+                            .or_insert_with(|| world.get_or_insert_chunk(*x, *z).serialize());
+                        (*x, *z, data.clone())
+                    })
+                    .collect()
+            });
+            let message = RustyCraftMessage::ChunkData { chunks };
+            self.send_or_drop(id, serialize_event(PlayerId::SERVER, message)).await;
+        }
+    }
+
+    // spawns or despawns a pair of players for each other
+    async fn set_visible(&mut self, a: PlayerId, b: PlayerId, visible: bool) {
+        for (viewer, shown) in [(a, b), (b, a)] {
+            let message = match self.clients.get_mut(viewer) {
+                Some(client) if visible => {
+                    client.visible.insert(shown);
+                    match self.clients.get(shown) {
+                        Some(shown) => spawn_message(shown),
+                        None => continue
+                    }
+                },
+                Some(client) => {
+                    client.visible.remove(&shown);
+                    RustyCraftMessage::DespawnPlayer
+                },
+                None => continue
+            };
+            self.send_or_drop(viewer, serialize_event(shown, message)).await;
+        }
+    }
+
+    // sends one message to several clients, encoding it only once
+    pub async fn send_to(&mut self, ids: &[PlayerId], message: RustyCraftMessage, sender_id: PlayerId) {
+        let event = serialize_event(sender_id, message);
+        for id in ids {
+            self.send_or_drop(*id, event.clone()).await;
+        }
+    }
+
+    pub async fn send_or_drop(&mut self, id: PlayerId, event: Arc<EncodedEvent>) {
+        let sent = match self.clients.get(id) {
+            Some(client) => client.send(event).await.is_ok(),
+            None => return
+        };
+        if !sent {
+            self.drop_clients(vec![id]).await;
+        }
+    }
+
+    // joined players, as opposed to connections still in the handshake
+    pub fn online(&self) -> usize {
+        self.clients.clients().filter(|c| c.name.is_some()).count()
+    }
+
+    pub async fn send_system_message(&mut self, id: PlayerId, content: String) {
+        let message = RustyCraftMessage::SystemMessage { content };
+        self.send_or_drop(id, serialize_event(PlayerId::SERVER, message)).await;
+    }
+
+    // records where every joined player is, so it is saved with the world
+    pub fn remember_players(&mut self) {
+        for client in self.clients.clients() {
+            if let Some(name) = &client.name {
+                self.world.players.update(name, player_data(client));
+            }
+        }
+    }
+
+    // removes clients and tells everyone else they left, following
+    // on to any clients that fail to receive that notice as well
+    pub async fn drop_clients(&mut self, mut ids: Vec<PlayerId>) {
+        while let Some(id) = ids.pop() {
+            let client = match self.clients.remove(id) {
+                Some(client) => client,
+                None => continue
+            };
+            client.close();
+            self.interest.remove(id);
+            for other in self.clients.clients_mut() {
+                other.visible.remove(&id);
+            }
+            let name = match &client.name {
+                Some(name) => name,
+                None => {
+                    log!(Debug, "Connection {} closed before joining", id);
+                    continue
+                }
+            };
+            log!(Info, "\u{001b}[33m{} left the server\u{001b}[0m", name);
+            self.history.record(&format!("{} left the game", name), true);
+            self.world.players.update(name, player_data(&client));
+            self.admission.set_online(self.online());
+            let failed = self.clients.broadcast(&RustyCraftMessage::Disconnect, id).await;
+            ids.extend(failed);
+        }
+    }
+}
+
+fn spawn_message(client: &Client) -> RustyCraftMessage {
+    RustyCraftMessage::SpawnPlayer { 
+        name: client.display_name(), 
+        x: client.x, 
+        y: client.y, 
+        z: client.z, 
+        yaw: client.yaw, 
+        pitch: client.pitch 
+    }
+}
+
+fn player_data(client: &Client) -> PlayerData {
+    PlayerData {
+        x: client.x,
+        y: client.y,
+        z: client.z,
+        yaw: client.yaw,
+        pitch: client.pitch,
+        last_seen: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+fn load_list<T>(load: fn(PathBuf) -> io::Result<T>, dir: &str, file: &str) -> T {
+    let path = format!("{}/{}", dir, file);
+    load(path.clone().into()).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+}
+
+fn reload_list<T: Serialize + DeserializeOwned + Default>(file: &mut JsonFile<T>, only_changed: bool) -> Result<bool, String> {
+    let reloaded = match only_changed {
+        true => file.reload_if_changed(),
+        false => file.reload().map(|_| true)
+    };
+    match reloaded {
+        Ok(true) => {
+            log!(Info, "\u{001b}[37mReloaded {}\u{001b}[0m", file.path().display());
+            Ok(true)
+        },
+        Ok(false) => Ok(false),
+        Err(e) => Err(format!("Failed to reload {}, keeping what was there before: {}", file.path().display(), e))
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{HashSet, VecDeque};
+
+// chunks out to this much past the view distance are kept, so a player
+// walking back and forth over a chunk border does not reload a whole row
+pub const UNLOAD_MARGIN: i32 = 1;
+
+// most chunks put in one ChunkData answering a GetChunks
+pub const CHUNKS_PER_MESSAGE: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct ChunkRequestConfig {
+    // most coordinates one GetChunks may list
+    pub max_coords: usize,
+    // requests per second a client can keep up
+    pub rate: f32,
+    // requests a client can send at once after being quiet
+    pub burst: f32
+}
+
+// whether a chunk is within distance chunks of a centre, on both axes
+pub fn in_range(center: (i32, i32), (x, z): (i32, i32), distance: i32) -> bool {
+    (x - center.0).abs() <= distance && (z - center.1).abs() <= distance
+}
+
+// coordinates of every chunk within radius of a centre, on both axes,
+// spiralling outwards so the nearest ones come first
+pub fn spiral((x, z): (i32, i32), radius: i32) -> Vec<(i32, i32)> {
+    let side = 2 * radius + 1;
+    let total = (side * side) as usize;
+    let mut coords = Vec::with_capacity(total);
+    coords.push((x, z));
+    let (mut dx, mut dz) = (0i32, 0i32);
+    // legs run right, down, left, up, and lengthen every second turn
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 1;
+    let mut turn = 0;
+    while coords.len() < total {
+        let (step_x, step_z) = directions[turn % 4];
+        for _ in 0..leg {
+            dx += step_x;
+            dz += step_z;
+            if dx.abs() <= radius && dz.abs() <= radius {
+                coords.push((x + dx, z + dz));
+            }
+        }
+        turn += 1;
+        if turn % 2 == 0 {
+            leg += 1;
+        }
+    }
+    coords
+}
+
+// which chunks a client has been sent and which it is still owed
+pub struct ChunkStream {
+    sent: HashSet<(i32, i32)>,
+    queue: VecDeque<(i32, i32)>,
+    // chunk the queue was built around
+    center: Option<(i32, i32)>
+}
+
+impl ChunkStream {
+    pub fn new() -> ChunkStream {
+        ChunkStream { sent: HashSet::new(), queue: VecDeque::new(), center: None }
+    }
+
+    // queues the chunks around a new centre the client does not have yet,
+    // and returns the ones it has that are now out of range
+    pub fn recenter(&mut self, center: (i32, i32), view_distance: i32) -> Vec<(i32, i32)> {
+        if self.center == Some(center) {
+            return Vec::new()
+        }
+        self.center = Some(center);
+        let keep = view_distance + UNLOAD_MARGIN;
+        let unloaded: Vec<(i32, i32)> = self.sent.iter()
+            .filter(|coords| !in_range(center, **coords, keep))
+            .copied()
+            .collect();
+        for coords in unloaded.iter() {
+            self.sent.remove(coords);
+        }
+        self.queue = spiral(center, view_distance).into_iter()
+            .filter(|coords| !self.sent.contains(coords))
+            .collect();
+        unloaded
+    }
+
+    // takes up to max queued chunks, skipping any sent since they were queued
+    pub fn next_batch(&mut self, max: usize) -> Vec<(i32, i32)> {
+        let mut batch = Vec::new();
+        while batch.len() < max {
+            match self.queue.pop_front() {
+                Some(coords) if self.sent.insert(coords) => batch.push(coords),
+                Some(_) => {},
+                None => break
+            }
+        }
+        batch
+    }
+
+    // for chunks the client asked for itself
+    pub fn mark_sent(&mut self, coords: (i32, i32)) {
+        self.sent.insert(coords);
+    }
+
+    pub fn has(&self, coords: (i32, i32)) -> bool {
+        self.sent.contains(&coords)
+    }
+
+    // for chunks the client missed a change to, so they are sent again
+    pub fn forget(&mut self, coords: (i32, i32)) {
+        if self.sent.remove(&coords) {
+            // queued again the next time the centre changes, or right away
+            self.center = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spiral_covers_the_square_nearest_first() {
+        let coords = spiral((5, -3), 2);
+        assert_eq!(coords.len(), 25);
+        assert_eq!(coords[0], (5, -3));
+        assert_eq!(coords.iter().collect::<HashSet<_>>().len(), 25);
+        assert!(coords.iter().all(|c| in_range((5, -3), *c, 2)));
+        let ring = |(x, z): (i32, i32)| (x - 5).abs().max((z + 3).abs());
+        assert!(coords.windows(2).all(|pair| ring(pair[0]) <= ring(pair[1])));
+    }
+
+    #[test]
+    fn chunks_past_the_margin_are_unloaded() {
+        let mut stream = ChunkStream::new();
+        assert!(stream.recenter((0, 0), 1).is_empty());
+        assert_eq!(stream.next_batch(100).len(), 9);
+
+        // one chunk past the view distance is kept
+        let mut unloaded = stream.recenter((2, 0), 1);
+        unloaded.sort();
+        assert_eq!(unloaded, vec![(-1, -1), (-1, 0), (-1, 1)]);
+        assert!(stream.has((0, 0)));
+        // and only the chunks it lacks are queued
+        assert_eq!(stream.next_batch(100).len(), 6);
+    }
+
+    #[test]
+    fn forgotten_chunks_are_sent_again() {
+        let mut stream = ChunkStream::new();
+        stream.recenter((0, 0), 1);
+        stream.next_batch(100);
+        stream.forget((1, 1));
+        assert!(!stream.has((1, 1)));
+        assert!(stream.recenter((0, 0), 1).is_empty());
+        assert_eq!(stream.next_batch(100), vec![(1, 1)]);
+    }
+}
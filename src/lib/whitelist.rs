@@ -0,0 +1,69 @@
+use std::{io, path::PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::rustycraft::files::JsonFile;
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct WhitelistEntries {
+    pub enabled: bool,
+    pub names: Vec<String>
+}
+
+// names allowed to join while whitelist mode is on, kept in
+// whitelist.json; names match ignoring case
+pub struct Whitelist {
+    file: JsonFile<WhitelistEntries>
+}
+
+impl Whitelist {
+    pub fn load(path: PathBuf) -> io::Result<Whitelist> {
+        Ok(Whitelist { file: JsonFile::load(path)? })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.file.value.enabled
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.file.value.names
+    }
+
+    // everyone is allowed while the whitelist is off
+    pub fn allows(&self, name: &str) -> bool {
+        !self.enabled() || self.contains(name)
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.file.value.names.iter().any(|allowed| allowed.to_lowercase() == name.to_lowercase())
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        self.file.value.enabled = enabled;
+        self.file.save()
+    }
+
+    // returns whether name was added rather than already listed
+    pub fn add(&mut self, name: &str) -> io::Result<bool> {
+        if self.contains(name) {
+            return Ok(false)
+        }
+        self.file.value.names.push(name.to_string());
+        self.file.save()?;
+        Ok(true)
+    }
+
+    // returns whether name was listed
+    pub fn remove(&mut self, name: &str) -> io::Result<bool> {
+        if !self.contains(name) {
+            return Ok(false)
+        }
+        self.file.value.names.retain(|allowed| allowed.to_lowercase() != name.to_lowercase());
+        self.file.save()?;
+        Ok(true)
+    }
+
+    pub fn file(&mut self) -> &mut JsonFile<WhitelistEntries> {
+        &mut self.file
+    }
+}
@@ -2,26 +2,120 @@ use super::block_type::BlockType;
 
 use super::chunk::{CHUNK_HEIGHT, CHUNK_SIZE};
 
-// x by y by z 3-dimensional map 
-// use vectors for serialization
+pub const SECTION_HEIGHT: usize = 16;
+const SECTION_COUNT: usize = CHUNK_HEIGHT / SECTION_HEIGHT;
+const SECTION_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * SECTION_HEIGHT;
+
+// 16x16x16 slice of a chunk storing a palette of the block types
+// it contains and, for every block, a bit-packed index into it
+#[derive(Clone)]
+struct Section {
+    palette: Vec<BlockType>,
+    // bits per index, 0 while the palette has a single entry
+    bits: u32,
+    // indices never straddle two words
+    data: Vec<u64>,
+    non_air: u16
+}
+
+impl Section {
+    fn new() -> Section {
+        Section { palette: vec![BlockType::Air], bits: 0, data: Vec::new(), non_air: 0 }
+    }
+
+    fn index(x: usize, y: usize, z: usize) -> usize {
+        (y * CHUNK_SIZE + z) * CHUNK_SIZE + x
+    }
+
+    fn palette_index(&self, i: usize) -> usize {
+        if self.bits == 0 {
+            return 0
+        }
+        let per_word = 64 / self.bits as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        ((self.data[i / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_palette_index(&mut self, i: usize, value: usize) {
+        let per_word = 64 / self.bits as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.data[i / per_word];
+        *word = (*word & !mask) | ((value as u64) << shift);
+    }
+
+    fn get(&self, i: usize) -> BlockType {
+        self.palette[self.palette_index(i)]
+    }
+
+    fn set(&mut self, i: usize, block: BlockType) {
+        let old = self.get(i);
+        if old == block {
+            return
+        }
+
+        let value = match self.palette.iter().position(|b| *b == block) {
+            Some(value) => value,
+            None => {
+                self.palette.push(block);
+                let needed = usize::BITS - (self.palette.len() - 1).leading_zeros();
+                if needed > self.bits {
+                    self.repack(needed);
+                }
+                self.palette.len() - 1
+            }
+        };
+        self.set_palette_index(i, value);
+
+        if old == BlockType::Air {
+            self.non_air += 1;
+        } else if block == BlockType::Air {
+            self.non_air -= 1;
+        }
+    }
+
+    fn repack(&mut self, bits: u32) {
+        let indices: Vec<usize> = (0..SECTION_VOLUME).map(|i| self.palette_index(i)).collect();
+        self.bits = bits;
+        self.data = vec![0; SECTION_VOLUME.div_ceil(64 / bits as usize)];
+        for (i, value) in indices.into_iter().enumerate() {
+            self.set_palette_index(i, value);
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Section>() 
+            + self.palette.capacity() * std::mem::size_of::<BlockType>() 
+            + self.data.capacity() * std::mem::size_of::<u64>()
+    }
+}
+
+// x by y by z 3-dimensional map, stored as vertical
+// sections with all-air sections left unallocated
 #[derive(Clone)]
 pub struct BlockMap {
-    map: [[[BlockType; CHUNK_HEIGHT]; CHUNK_SIZE]; CHUNK_SIZE]
+    sections: [Option<Box<Section>>; SECTION_COUNT]
 }
 
 impl BlockMap {
     pub fn new() -> BlockMap {
-        let map = [[[BlockType::Air; CHUNK_HEIGHT]; CHUNK_SIZE]; CHUNK_SIZE];
-        BlockMap { map }
+        BlockMap { sections: Default::default() }
     }
 
     pub fn get(&self, x: usize, y: usize, z: usize) -> BlockType {
-        self.map[x][z][y]
+        match &self.sections[y / SECTION_HEIGHT] {
+            Some(section) => section.get(Section::index(x, y % SECTION_HEIGHT, z)),
+            None => BlockType::Air
+        }
     }
 
     pub fn highest_in_column(&self, x: usize, z: usize) -> usize {
         for i in 1..CHUNK_HEIGHT {
             let y = CHUNK_HEIGHT - i;
+            // skip whole sections of air
+            if self.sections[y / SECTION_HEIGHT].is_none() {
+                continue;
+            }
             if self.get(x, y, z) != BlockType::Air {
                 return y
             }
@@ -29,19 +123,148 @@ impl BlockMap {
         0
     }
 
+    // whether the 16x16 layer at y holds nothing but air
+    pub fn layer_is_empty(&self, y: usize) -> bool {
+        if self.sections[y / SECTION_HEIGHT].is_none() {
+            return true
+        }
+        (0..CHUNK_SIZE).all(|x| (0..CHUNK_SIZE).all(|z| self.get(x, y, z) == BlockType::Air))
+    }
+
     pub fn set(&mut self, x: usize, y: usize, z: usize, block: BlockType) {
         if x >= CHUNK_SIZE {
-            panic!(format!("Segfault, attempted to read map at invalid x: {}", x))
+            panic!("Segfault, attempted to read map at invalid x: {}", x)
         }
 
         if y >= CHUNK_HEIGHT {
-            panic!(format!("Segfault, attempted to read map at invalid y: {}", y))
+            panic!("Segfault, attempted to read map at invalid y: {}", y)
         }
 
         if z >= CHUNK_SIZE {
-            panic!(format!("Segfault, attempted to read map at invalid z: {}", z))
+            panic!("Segfault, attempted to read map at invalid z: {}", z)
+        }
+
+        let slot = &mut self.sections[y / SECTION_HEIGHT];
+        if slot.is_none() {
+            if block == BlockType::Air {
+                return
+            }
+            *slot = Some(Box::new(Section::new()));
         }
 
-        self.map[x][z][y] = block;
+        let section = slot.as_mut().unwrap();
+        section.set(Section::index(x, y % SECTION_HEIGHT, z), block);
+        if section.non_air == 0 {
+            *slot = None;
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<BlockMap>() + self.sections.iter().flatten().map(|s| s.memory_usage()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use super::super::block_type::index_to_block;
+
+    // the fixed array layout BlockMap replaced, to check against
+    struct Reference(Vec<BlockType>);
+
+    impl Reference {
+        fn new() -> Reference {
+            Reference(vec![BlockType::Air; CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE])
+        }
+
+        fn index(x: usize, y: usize, z: usize) -> usize {
+            (x * CHUNK_SIZE + z) * CHUNK_HEIGHT + y
+        }
+
+        fn get(&self, x: usize, y: usize, z: usize) -> BlockType {
+            self.0[Reference::index(x, y, z)]
+        }
+
+        fn set(&mut self, x: usize, y: usize, z: usize, block: BlockType) {
+            self.0[Reference::index(x, y, z)] = block;
+        }
+
+        fn highest_in_column(&self, x: usize, z: usize) -> usize {
+            (1..CHUNK_HEIGHT).rev().find(|y| self.get(x, *y, z) != BlockType::Air).unwrap_or(0)
+        }
     }
-}
\ No newline at end of file
+
+    fn assert_matches(map: &BlockMap, reference: &Reference) {
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_HEIGHT {
+                    assert_eq!(map.get(x, y, z), reference.get(x, y, z), "block at {}, {}, {}", x, y, z);
+                }
+                assert_eq!(map.highest_in_column(x, z), reference.highest_in_column(x, z), "column {}, {}", x, z);
+            }
+        }
+        for y in 0..CHUNK_HEIGHT {
+            let empty = (0..CHUNK_SIZE).all(|x| (0..CHUNK_SIZE).all(|z| reference.get(x, y, z) == BlockType::Air));
+            assert_eq!(map.layer_is_empty(y), empty, "layer {}", y);
+        }
+    }
+
+    #[test]
+    fn section_repacks_as_its_palette_grows() {
+        let mut section = Section::new();
+        let mut expected = vec![BlockType::Air; SECTION_VOLUME];
+        let blocks: Vec<BlockType> = (0..16).filter_map(index_to_block).filter(|b| *b != BlockType::Air).collect();
+        for (n, block) in blocks.iter().enumerate() {
+            // a different stride each time so every word holds a mix
+            for i in (n..SECTION_VOLUME).step_by(n + 2) {
+                section.set(i, *block);
+                expected[i] = *block;
+            }
+            let needed = usize::BITS - (section.palette.len() - 1).leading_zeros();
+            assert_eq!(section.bits, needed, "after adding {:?}", block);
+            assert!((0..SECTION_VOLUME).all(|i| section.get(i) == expected[i]), "after adding {:?}", block);
+        }
+        assert_eq!(section.palette.len(), 16);
+        assert_eq!(section.bits, 4);
+        assert_eq!(section.non_air as usize, expected.iter().filter(|b| **b != BlockType::Air).count());
+    }
+
+    #[test]
+    fn matches_the_fixed_array_layout() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut map = BlockMap::new();
+        let mut reference = Reference::new();
+        assert_matches(&map, &reference);
+
+        for round in 0..4 {
+            for _ in 0..5000 {
+                let (x, z) = (rng.gen_range(0..CHUNK_SIZE), rng.gen_range(0..CHUNK_SIZE));
+                // mostly low down, like terrain, with the odd block up high
+                let y = match rng.gen_bool(0.9) {
+                    true => rng.gen_range(0..64),
+                    false => rng.gen_range(0..CHUNK_HEIGHT)
+                };
+                // later rounds clear more than they place, emptying sections again
+                let block = match rng.gen_bool(0.25 + round as f64 * 0.2) {
+                    true => BlockType::Air,
+                    false => index_to_block(rng.gen_range(0..16)).unwrap()
+                };
+                map.set(x, y, z, block);
+                reference.set(x, y, z, block);
+            }
+            assert_matches(&map, &reference);
+        }
+    }
+
+    #[test]
+    fn clearing_a_section_frees_it() {
+        let mut map = BlockMap::new();
+        map.set(3, 40, 7, BlockType::Stone);
+        assert!(map.sections[2].is_some());
+        map.set(3, 40, 7, BlockType::Air);
+        assert!(map.sections[2].is_none());
+        assert_eq!(map.highest_in_column(3, 7), 0);
+    }
+}
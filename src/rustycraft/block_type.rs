@@ -20,6 +20,13 @@ pub enum BlockType {
     Brick
 }
 
+impl BlockType {
+    // whether players collide with the block
+    pub fn is_solid(&self) -> bool {
+        !matches!(self, BlockType::Air | BlockType::Water)
+    }
+}
+
 pub fn index_to_block(index: usize) -> Option<BlockType> {
     match index {
         0 => Some(BlockType::Grass),
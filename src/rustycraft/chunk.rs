@@ -1,41 +1,28 @@
-use std::fs;
 use noise::{NoiseFn, OpenSimplex};
 use rand::prelude::*;
 use crate::{rustycraft::{block_map::BlockMap, block_type::BlockType}};
-use super::chunk_utils::{from_serialized, to_serialized};
+use super::chunk_utils::{decode_chunk, encode_chunk, to_serialized, ChunkFormatError};
 
 pub const CHUNK_SIZE: usize = 16;
 pub const CHUNK_HEIGHT: usize = 256;
+pub const DEFAULT_TERRAIN_AMPLITUDE: f32 = 15.0;
 
 #[derive(Clone)]
 pub struct Chunk {
-    pub blocks: BlockMap,
-    pub blocks_in_mesh: Vec<(usize, usize, usize)>,
-    x: i32,
-    z: i32,
-    save_path: String
+    pub blocks: BlockMap
     //pub serialized_blocks: String
 }
 
 impl Chunk {
-    pub fn from(save_path: String, contents: String, x: i32, z: i32) -> Chunk {
-        // follows format (single line)
-        // [amount if > 1][num][block][amount if > 1][num][block]...
-        let (blocks_in_mesh, blocks) = from_serialized(&contents);
-        let chunk = Chunk { blocks, blocks_in_mesh, x: x * 16, z: z * 16, save_path };
-        chunk
+    // decodes a chunk as stored on disk, in either the current or the legacy format
+    pub fn decode(bytes: &[u8]) -> Result<Chunk, ChunkFormatError> {
+        let blocks = decode_chunk(bytes)?;
+        Ok(Chunk { blocks })
     }
 
-    pub fn new(x_offset: i32, z_offset: i32, simplex: OpenSimplex, chunk_dir: String) -> Chunk {
-        let save_path = format!("{}/{}_{}", chunk_dir, x_offset, z_offset);
-        let contents = fs::read_to_string(save_path.clone());
-        if let Ok(contents) = contents {
-            return Chunk::from(save_path, contents, x_offset, z_offset)
-        }
-
-        let amplitude = 15.0;
+    // generates a fresh chunk, loading saved ones is up to World
+    pub fn new(x_offset: i32, z_offset: i32, simplex: OpenSimplex, amplitude: f32) -> Chunk {
         let mut blocks = BlockMap::new();
-        let mut blocks_in_mesh = Vec::new();
         let x_offset = x_offset * 16;
         let z_offset = z_offset * 16;
         for x in 0..CHUNK_SIZE {
@@ -51,7 +38,7 @@ impl Chunk {
                         } else {
                             BlockType::Water
                         };
-                        add_block(&mut blocks, &mut blocks_in_mesh, x, y, z, block);
+                        blocks.set(x, y, z, block);
                     }
                 } else {
                     let snow_offset = (sample(simplex_x * 4.0, simplex_z * 4.0, simplex) * 20.0) as usize;
@@ -75,7 +62,7 @@ impl Chunk {
                         } else {
                             BlockType::Stone
                         };
-                        add_block(&mut blocks, &mut blocks_in_mesh, x, y, z, block);
+                        blocks.set(x, y, z, block);
                     }
                 }
             }
@@ -91,52 +78,49 @@ impl Chunk {
             if block != BlockType::Water && block != BlockType::Stone && block != BlockType::Sand && block != BlockType::Snow {
                 // trunk
                 for i in 1..4 {
-                    add_block(&mut blocks, &mut blocks_in_mesh, x, top + i, z, BlockType::Log);
+                    blocks.set(x, top + i, z, BlockType::Log);
                 }
                
                 // leaf layer
                 for ix in 0..3 {
                     for iz in 0..3 {
-                        add_block(&mut blocks, &mut blocks_in_mesh, x + 1 - ix, top + 3, z + 1 - iz, BlockType::Leaves);
+                        blocks.set(x + 1 - ix, top + 3, z + 1 - iz, BlockType::Leaves);
                     }
                 }
 
                 // second layer
-                add_block(&mut blocks, &mut blocks_in_mesh, x, top + 4, z, BlockType::Leaves);
-                add_block(&mut blocks, &mut blocks_in_mesh, x + 1, top + 4, z, BlockType::Leaves);
-                add_block(&mut blocks, &mut blocks_in_mesh, x - 1, top + 4, z, BlockType::Leaves);
-                add_block(&mut blocks, &mut blocks_in_mesh, x, top + 4, z + 1, BlockType::Leaves);
-                add_block(&mut blocks, &mut blocks_in_mesh, x, top + 4, z - 1, BlockType::Leaves);
+                blocks.set(x, top + 4, z, BlockType::Leaves);
+                blocks.set(x + 1, top + 4, z, BlockType::Leaves);
+                blocks.set(x - 1, top + 4, z, BlockType::Leaves);
+                blocks.set(x, top + 4, z + 1, BlockType::Leaves);
+                blocks.set(x, top + 4, z - 1, BlockType::Leaves);
 
                 // highest leaf block
-                add_block(&mut blocks, &mut blocks_in_mesh, x, top + 5, z, BlockType::Leaves);
+                blocks.set(x, top + 5, z, BlockType::Leaves);
             }
         }
 
 
-        let chunk = Chunk { blocks, blocks_in_mesh, x: x_offset, z: z_offset, save_path };
-        chunk.save();
-        chunk
+        Chunk { blocks }
+    }
+
+    // rough heap and inline footprint, for monitoring
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Chunk>() - std::mem::size_of::<BlockMap>() + self.blocks.memory_usage()
+    }
+
+    // layout sent to clients in ChunkData
+    pub fn serialize(&self) -> String {
+        to_serialized(&self.blocks)
     }
 
-    fn save(&self) {
-        fs::write(self.save_path.clone(), to_serialized(&self.blocks_in_mesh, &self.blocks))
-            .expect(format!("Failed to save chunk to {}", self.save_path.clone()).as_str());
+    // layout written to region files
+    pub fn encode(&self) -> Vec<u8> {
+        encode_chunk(&self.blocks)
     }
 
     pub fn set_block(&mut self, x: usize, y: usize, z: usize, block: BlockType) {
         self.blocks.set(x, y, z, block);
-        if block == BlockType::Air {
-            for i in 0..self.blocks_in_mesh.len() - 1 {
-                if self.blocks_in_mesh[i] == (x, y, z) {
-                    self.blocks_in_mesh.remove(i);
-                    break;
-                }
-            }
-        } else {
-            self.blocks_in_mesh.push((x, y, z));
-        }
-        self.save();
     }
 
     pub fn highest_in_column(&self, x: usize, z: usize) -> usize {
@@ -160,8 +144,3 @@ fn sample(x: f32, z: f32, simplex: OpenSimplex) -> f32 {
     // so shift over to 0.0 to 1.0 range
     ((simplex.get([x as f64, z as f64]) + 1.0) / 2.0) as f32
 }
-
-fn add_block(blocks: &mut BlockMap, blocks_in_mesh: &mut Vec<(usize, usize, usize)>, x: usize, y: usize, z: usize, block: BlockType) {
-    blocks.set(x, y, z, block);
-    blocks_in_mesh.push((x, y, z));
-}
\ No newline at end of file
@@ -1,58 +1,243 @@
-use std::collections::HashSet;
-use crate::rustycraft::{block_map::BlockMap, block_type::index_to_block};
+use std::fmt;
 
-type BlocksInMesh = Vec<(usize, usize, usize)>;
+use crate::rustycraft::{block_map::BlockMap, block_type::index_to_block, chunk::{CHUNK_HEIGHT, CHUNK_SIZE}};
 
-pub fn from_serialized(serialized: &String) -> (BlocksInMesh, BlockMap) {
+// version of the layout produced by to_serialized,
+// advertised to clients during the handshake
+pub const CHUNK_FORMAT_VERSION: u32 = 1;
+
+// on-disk chunk format
+// magic (4) | version u8 | payload length u32 | crc32 of payload u32 | payload
+// version 1 payload: layer count u16, then per layer y u8 and a 16x16 grid of block ids
+pub const CHUNK_MAGIC: &[u8; 4] = b"RCCK";
+pub const CHUNK_DISK_VERSION: u8 = 1;
+const CHUNK_HEADER_SIZE: usize = 13;
+const LAYER_SIZE: usize = CHUNK_SIZE * CHUNK_SIZE;
+
+#[derive(Debug)]
+pub enum ChunkFormatError {
+    Truncated,
+    UnsupportedVersion(u8),
+    ChecksumMismatch,
+    InvalidY(usize),
+    InvalidBlock(u8)
+}
+
+impl fmt::Display for ChunkFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkFormatError::Truncated => write!(f, "chunk data is truncated"),
+            ChunkFormatError::UnsupportedVersion(version) => write!(f, "unsupported chunk format version {}", version),
+            ChunkFormatError::ChecksumMismatch => write!(f, "chunk checksum mismatch"),
+            ChunkFormatError::InvalidY(y) => write!(f, "layer y {} is out of range", y),
+            ChunkFormatError::InvalidBlock(id) => write!(f, "unknown block id {}", id)
+        }
+    }
+}
+
+pub fn encode_chunk(blocks: &BlockMap) -> Vec<u8> {
+    let layers: Vec<usize> = (0..CHUNK_HEIGHT).filter(|y| !blocks.layer_is_empty(*y)).collect();
+    let mut payload = Vec::with_capacity(2 + layers.len() * (1 + LAYER_SIZE));
+    payload.extend_from_slice(&(layers.len() as u16).to_le_bytes());
+    for y in layers {
+        payload.push(y as u8);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                payload.push(blocks.get(x, y, z) as u8);
+            }
+        }
+    }
+
+    let mut encoded = Vec::with_capacity(CHUNK_HEADER_SIZE + payload.len());
+    encoded.extend_from_slice(CHUNK_MAGIC);
+    encoded.push(CHUNK_DISK_VERSION);
+    encoded.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    encoded.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    encoded.extend_from_slice(&payload);
+    encoded
+}
+
+// reads both the current format and the legacy headerless one
+pub fn decode_chunk(bytes: &[u8]) -> Result<BlockMap, ChunkFormatError> {
+    if !bytes.starts_with(CHUNK_MAGIC) {
+        return from_serialized(bytes)
+    }
+    if bytes.len() < CHUNK_HEADER_SIZE {
+        return Err(ChunkFormatError::Truncated)
+    }
+
+    let version = bytes[4];
+    if version != CHUNK_DISK_VERSION {
+        return Err(ChunkFormatError::UnsupportedVersion(version))
+    }
+    let length = u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
+    let crc = u32::from_le_bytes([bytes[9], bytes[10], bytes[11], bytes[12]]);
+    let payload = bytes[CHUNK_HEADER_SIZE..].get(..length).ok_or(ChunkFormatError::Truncated)?;
+    if crc32fast::hash(payload) != crc {
+        return Err(ChunkFormatError::ChecksumMismatch)
+    }
+
+    if payload.len() < 2 {
+        return Err(ChunkFormatError::Truncated)
+    }
+    let layer_count = u16::from_le_bytes([payload[0], payload[1]]) as usize;
+    let mut blocks = BlockMap::new();
+    let mut layers = payload[2..].chunks_exact(1 + LAYER_SIZE);
+    for _ in 0..layer_count {
+        let layer = layers.next().ok_or(ChunkFormatError::Truncated)?;
+        let y = layer[0] as usize;
+        for (i, id) in layer[1..].iter().enumerate() {
+            let block = index_to_block(*id as usize).ok_or(ChunkFormatError::InvalidBlock(*id))?;
+            blocks.set(i / CHUNK_SIZE, y, i % CHUNK_SIZE, block);
+        }
+    }
+    Ok(blocks)
+}
+
+// legacy format, still what clients receive in ChunkData
+pub fn from_serialized(bytes: &[u8]) -> Result<BlockMap, ChunkFormatError> {
     // format (127 as delimiter between layers)
-    // 127 <y_greater_than_1> <y mod 127> 16x16 layer grid ...
-    let mut blocks_in_mesh = Vec::new();
+    // 127 <y / 127> <y % 127> 16x16 layer grid ...
     let mut blocks = BlockMap::new();
-    let bytes = serialized.as_bytes();
     let mut i = 0;
     let mut y = 0;
     let mut iter_in_layer = 0;
     while i < bytes.len() {
         let byte = bytes[i];
         if byte == 127 {
-            y = bytes[i + 1] * 127 + bytes[i + 2];
+            let (high, low) = match bytes.get(i + 1..i + 3) {
+                Some(pair) => (pair[0] as usize, pair[1] as usize),
+                None => return Err(ChunkFormatError::Truncated)
+            };
+            y = high * 127 + low;
+            if y >= CHUNK_HEIGHT {
+                return Err(ChunkFormatError::InvalidY(y))
+            }
             iter_in_layer = 0;
             i += 2;
         } else {
+            if iter_in_layer >= LAYER_SIZE {
+                return Err(ChunkFormatError::Truncated)
+            }
             let x = iter_in_layer / 16;
             let z = iter_in_layer % 16;
-            blocks_in_mesh.push((x, y as usize, z));
-            let block = index_to_block(byte as usize);
-            blocks.set(x, y as usize, z, block.unwrap());
+            let block = index_to_block(byte as usize).ok_or(ChunkFormatError::InvalidBlock(byte))?;
+            blocks.set(x, y, z, block);
             iter_in_layer += 1;
         }
         i += 1;
     }
-    (blocks_in_mesh, blocks)
+    Ok(blocks)
 }
 
-pub fn to_serialized(blocks_in_mesh: &BlocksInMesh, blocks: &BlockMap) -> String {
-    // map y to list of x, z and block tuples
-    let mut layer_ys = HashSet::new();
-    for (_, y, _) in blocks_in_mesh.iter() {
-        layer_ys.insert(*y);
-    }
-
+pub fn to_serialized(blocks: &BlockMap) -> String {
     let mut serialized = String::new();
-    for y in layer_ys.iter() {
-        // use 255 as delimiter, ignored in RLE compression
-        serialized.push(127 as u8 as char);
-        let y = *y as u8;
-        // need two chars to represent 0-255
-        let has_127 = if y > 127 { 1u8 } else { 0u8 };
-        serialized.push(has_127 as char);
-        serialized.push((y % 127) as char);
+    for y in (0..CHUNK_HEIGHT).filter(|y| !blocks.layer_is_empty(*y)) {
+        // use 127 as delimiter, ignored in RLE compression
+        serialized.push(127u8 as char);
+        // need two chars to represent 0-255 without using 127,
+        // readers compute y as high * 127 + low
+        serialized.push((y / 127) as u8 as char);
+        serialized.push((y % 127) as u8 as char);
         for x in 0..16 {
             for z in 0..16 {
-                let block = blocks.get(x, y as usize, z);
+                let block = blocks.get(x, y, z);
                 serialized.push(block as u8 as char);
             }
         }
     }
     serialized
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::rustycraft::block_type::BlockType;
+
+    const BLOCK_IDS: usize = 16;
+
+    // every non-empty layer is written out in full and empty ones are
+    // skipped, so two maps hold the same blocks exactly when they encode
+    // to the same bytes, which is much quicker than comparing every block
+    fn assert_same(a: &BlockMap, b: &BlockMap) {
+        assert_eq!(encode_chunk(a), encode_chunk(b));
+    }
+
+    // returns what the current and legacy readers made of it
+    fn round_trips(blocks: &BlockMap) -> [BlockMap; 3] {
+        let decoded = decode_chunk(&encode_chunk(blocks)).unwrap();
+        let legacy = to_serialized(blocks);
+        let from_legacy = from_serialized(legacy.as_bytes()).unwrap();
+        // the current reader still takes the legacy format
+        let decoded_legacy = decode_chunk(legacy.as_bytes()).unwrap();
+        for decoded in [&decoded, &from_legacy, &decoded_legacy] {
+            assert_same(decoded, blocks);
+        }
+        [decoded, from_legacy, decoded_legacy]
+    }
+
+    #[test]
+    fn every_height_and_block_round_trips() {
+        for y in 0..CHUNK_HEIGHT {
+            // each id once, along a diagonal that reaches both ends of x and z
+            let placed: Vec<(usize, usize, BlockType)> = (0..BLOCK_IDS)
+                .map(|id| (id, CHUNK_SIZE - 1 - id, index_to_block(id).unwrap()))
+                .collect();
+            let mut blocks = BlockMap::new();
+            for (x, z, block) in placed.iter() {
+                blocks.set(*x, y, *z, *block);
+            }
+            for decoded in round_trips(&blocks) {
+                for (x, z, block) in placed.iter() {
+                    assert_eq!(decoded.get(*x, y, *z), *block, "block at {}, {}, {}", x, y, z);
+                }
+                assert_eq!(decoded.get(0, y, 0), BlockType::Air);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_chunk_round_trips() {
+        round_trips(&BlockMap::new());
+    }
+
+    // x, y, z and block id of each block placed, later ones overwriting earlier
+    fn placements() -> impl Strategy<Value = Vec<(usize, usize, usize, usize)>> {
+        prop::collection::vec((0..CHUNK_SIZE, 0..CHUNK_HEIGHT, 0..CHUNK_SIZE, 0..BLOCK_IDS), 0..1000)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn random_chunks_round_trip(placed in placements()) {
+            let mut blocks = BlockMap::new();
+            for (x, y, z, id) in placed {
+                blocks.set(x, y, z, index_to_block(id).unwrap());
+            }
+            round_trips(&blocks);
+        }
+
+        #[test]
+        fn full_layers_round_trip(y in 0..CHUNK_HEIGHT, ids in prop::collection::vec(0..BLOCK_IDS, LAYER_SIZE)) {
+            let mut blocks = BlockMap::new();
+            for (i, id) in ids.into_iter().enumerate() {
+                blocks.set(i / CHUNK_SIZE, y, i % CHUNK_SIZE, index_to_block(id).unwrap());
+            }
+            round_trips(&blocks);
+        }
+    }
+
+    #[test]
+    fn delimiter_never_appears_in_legacy_layers() {
+        let mut blocks = BlockMap::new();
+        for y in 0..CHUNK_HEIGHT {
+            blocks.set(0, y, 0, BlockType::Brick);
+        }
+        let legacy = to_serialized(&blocks);
+        let delimiters = legacy.bytes().filter(|byte| *byte == 127).count();
+        assert_eq!(delimiters, CHUNK_HEIGHT);
+    }
+}
@@ -12,21 +12,11 @@ impl<T> CoordMap<T> {
     }
 
     pub fn get(&self, x: i32, z: i32) -> Option<&T> {
-        let item = self.map.get(&x);
-        if item.is_none() {
-            return None
-        }
-
-        item.unwrap().get(&z)
+        self.map.get(&x)?.get(&z)
     }
 
     pub fn get_mut(&mut self, x: i32, z: i32) -> Option<&mut T> {
-        let item = self.map.get_mut(&x);
-        if item.is_none() {
-            return None
-        }
-
-        item.unwrap().get_mut(&z)
+        self.map.get_mut(&x)?.get_mut(&z)
     }
 
     pub fn contains(&self, x: i32, z: i32) -> bool {
@@ -39,9 +29,23 @@ impl<T> CoordMap<T> {
     }
 
     pub fn insert(&mut self, x: i32, z: i32, value: T) {
-        if !self.map.contains_key(&x) {
-            self.map.insert(x, HashMap::new());
+        self.map.entry(x).or_default().insert(z, value);
+    }
+
+    pub fn remove(&mut self, x: i32, z: i32) -> Option<T> {
+        let column = self.map.get_mut(&x)?;
+        let item = column.remove(&z);
+        if column.is_empty() {
+            self.map.remove(&x);
         }
-        self.map.get_mut(&x).unwrap().insert(z, value);        
+        item
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.values().map(|column| column.len()).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i32, i32, &T)> {
+        self.map.iter().flat_map(|(x, column)| column.iter().map(move |(z, item)| (*x, *z, item)))
     }
 }
\ No newline at end of file
@@ -0,0 +1,82 @@
+use std::{fs::{self, File}, io::{self, Write}, path::{Path, PathBuf}, time::SystemTime};
+use serde::{Serialize, de::DeserializeOwned};
+
+// writes to a temporary file and renames it over path, so a crash
+// part way through leaves either the old or the new contents
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    {
+        let mut temp = File::create(&temp_path)?;
+        temp.write_all(contents)?;
+        temp.sync_all()?;
+    }
+    fs::rename(&temp_path, path)?;
+    // make the rename itself durable where directories can be synced
+    if let Some(Ok(dir)) = path.parent().map(File::open) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+// reads a JSON file, or the default value if it does not exist
+pub fn read_json<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e)
+    }
+}
+
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let contents = serde_json::to_vec_pretty(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_atomic(path, &contents)
+}
+
+// a JSON file kept in memory that can be picked up again after
+// being edited by hand while the server is running
+pub struct JsonFile<T> {
+    path: PathBuf,
+    // as of the last read or write, None if the file did not exist
+    modified: Option<SystemTime>,
+    pub value: T
+}
+
+impl<T: Serialize + DeserializeOwned + Default> JsonFile<T> {
+    pub fn load(path: PathBuf) -> io::Result<JsonFile<T>> {
+        let modified = modified_time(&path);
+        let value = read_json(&path)?;
+        Ok(JsonFile { path, modified, value })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        write_json(&self.path, &self.value)?;
+        self.modified = modified_time(&self.path);
+        Ok(())
+    }
+
+    // reads the file again; on error the old value is kept
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.modified = modified_time(&self.path);
+        self.value = read_json(&self.path)?;
+        Ok(())
+    }
+
+    // returns whether the file had changed and was read again
+    pub fn reload_if_changed(&mut self) -> io::Result<bool> {
+        if modified_time(&self.path) == self.modified {
+            return Ok(false)
+        }
+        self.reload()?;
+        Ok(true)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
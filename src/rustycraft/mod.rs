@@ -4,4 +4,8 @@ pub mod chunk;
 pub mod block_map;
 pub mod coord_map;
 pub mod world;
-pub mod chunk_utils;
\ No newline at end of file
+pub mod chunk_utils;
+pub mod region;
+pub mod files;
+pub mod player_data;
+pub mod spawn;
\ No newline at end of file
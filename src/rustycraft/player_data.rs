@@ -0,0 +1,162 @@
+use std::{collections::HashMap, fs, io, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::files::{read_json, write_json};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct PlayerData {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    // unix time in seconds
+    pub last_seen: u64
+}
+
+impl PlayerData {
+    // JSON has no infinity or NaN, so they would be saved as null
+    // and the entry could not be read back
+    fn is_finite(&self) -> bool {
+        [self.x, self.y, self.z, self.yaw, self.pitch].iter().all(|value| value.is_finite())
+    }
+}
+
+// last known state of every player that has joined the
+// world, keyed by name and kept in players.json
+pub struct PlayerStore {
+    path: PathBuf,
+    players: HashMap<String, PlayerData>,
+    dirty: bool
+}
+
+impl PlayerStore {
+    // entries that cannot be read are dropped, and a file that is not
+    // JSON at all is moved aside to players.json.bad, so one bad save
+    // never stops the world from loading
+    pub fn load(path: PathBuf) -> io::Result<PlayerStore> {
+        let entries: HashMap<String, Value> = match read_json(&path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let moved = path.with_extension("json.bad");
+                log!(Error, "\u{001b}[31mCould not read {}, moving it to {}: {}\u{001b}[0m", path.display(), moved.display(), e);
+                fs::rename(&path, &moved)?;
+                HashMap::new()
+            },
+            Err(e) => return Err(e)
+        };
+
+        let mut players = HashMap::new();
+        let mut dirty = false;
+        for (name, entry) in entries {
+            match serde_json::from_value::<PlayerData>(entry) {
+                Ok(data) if data.is_finite() => {
+                    players.insert(name, data);
+                },
+                _ => {
+                    log!(Warn, "\u{001b}[31mDropping unreadable player data for {:?} in {}\u{001b}[0m", name, path.display());
+                    dirty = true;
+                }
+            }
+        }
+        Ok(PlayerStore { path, players, dirty })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PlayerData> {
+        self.players.get(name)
+    }
+
+    // data that is not finite is not kept, leaving whatever was saved before
+    pub fn update(&mut self, name: &str, data: PlayerData) {
+        if !data.is_finite() {
+            log!(Warn, "\u{001b}[31mNot saving player data for {} that is not a finite number\u{001b}[0m", name);
+            return
+        }
+        self.players.insert(name.to_string(), data);
+        self.dirty = true;
+    }
+
+    // writes players.json if anything changed since the last save
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(())
+        }
+        write_json(&self.path, &self.players)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    // players.json in a fresh directory, removed on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            let dir = env::temp_dir().join(format!("rustycraft-players-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempFile(dir.join("players.json"))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    fn data(yaw: f32) -> PlayerData {
+        PlayerData { x: 1.0, y: 60.0, z: -3.5, yaw, pitch: 10.0, last_seen: 1000 }
+    }
+
+    #[test]
+    fn non_finite_data_is_not_saved() {
+        let file = TempFile::new("finite");
+        let mut store = PlayerStore::load(file.0.clone()).unwrap();
+        store.update("Alex", data(-90.0));
+        store.update("Alex", data(f32::INFINITY));
+        store.update("Sam", data(f32::NAN));
+        store.save().unwrap();
+
+        let store = PlayerStore::load(file.0.clone()).unwrap();
+        assert_eq!(store.get("Alex").unwrap().yaw, -90.0);
+        assert!(store.get("Sam").is_none());
+    }
+
+    #[test]
+    fn unreadable_entries_are_dropped() {
+        let file = TempFile::new("entries");
+        fs::write(&file.0, r#"{
+            "Alex": {"x": 1.0, "y": 60.0, "z": -3.5, "yaw": -90.0, "pitch": 10.0, "last_seen": 1000},
+            "Sam": {"x": 1.0, "y": 60.0, "z": -3.5, "yaw": null, "pitch": 10.0, "last_seen": 1000},
+            "Kim": {"x": "far"},
+            "Lee": 7
+        }"#).unwrap();
+
+        let mut store = PlayerStore::load(file.0.clone()).unwrap();
+        assert_eq!(store.get("Alex").unwrap().x, 1.0);
+        assert!(store.get("Sam").is_none() && store.get("Kim").is_none() && store.get("Lee").is_none());
+
+        // the next save writes the file out without them
+        store.save().unwrap();
+        let saved: HashMap<String, Value> = read_json(&file.0).unwrap();
+        assert_eq!(saved.keys().collect::<Vec<_>>(), vec!["Alex"]);
+    }
+
+    #[test]
+    fn unparseable_file_is_moved_aside() {
+        let file = TempFile::new("garbage");
+        fs::write(&file.0, "{\"Alex\": {\"x\": 1.0,").unwrap();
+        let store = PlayerStore::load(file.0.clone()).unwrap();
+        assert!(store.get("Alex").is_none());
+        assert!(!file.0.exists());
+        assert_eq!(fs::read_to_string(file.0.with_extension("json.bad")).unwrap(), "{\"Alex\": {\"x\": 1.0,");
+    }
+}
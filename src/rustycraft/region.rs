@@ -0,0 +1,657 @@
+use std::{collections::HashMap, fs::{self, File, OpenOptions}, io::{self, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+
+// region files group REGION_SIZE x REGION_SIZE chunks; each starts with
+// an offset table of (sector offset, sector count) pairs, one per chunk,
+// followed by chunk records aligned to SECTOR_SIZE
+pub const REGION_SIZE: i32 = 32;
+const CHUNKS_PER_REGION: usize = (REGION_SIZE * REGION_SIZE) as usize;
+const SECTOR_SIZE: u64 = 4096;
+const ENTRY_SIZE: usize = 8;
+const HEADER_SIZE: u64 = (CHUNKS_PER_REGION * ENTRY_SIZE) as u64;
+const HEADER_SECTORS: u32 = (HEADER_SIZE / SECTOR_SIZE) as u32;
+
+// each record is [payload length: u32][compression: u8][crc32 of payload: u32][payload]
+const RECORD_HEADER_SIZE: usize = 9;
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_ZLIB: u8 = 1;
+
+// region files kept open at once, past this the least recently used is closed
+const MAX_OPEN_REGIONS: usize = 16;
+
+#[derive(Copy, Clone, Default)]
+struct Entry {
+    offset: u32,
+    sectors: u32
+}
+
+impl Entry {
+    fn is_empty(&self) -> bool {
+        self.sectors == 0
+    }
+}
+
+pub struct Region {
+    // None until the region is first saved
+    file: Option<File>,
+    path: PathBuf,
+    entries: Vec<Entry>,
+    // one flag per sector in the file, header sectors included
+    used: Vec<bool>,
+    // sectors of corrupt records, only reused once the
+    // offset table no longer pointing at them is on disk
+    pending_free: Vec<Entry>
+}
+
+impl Region {
+    pub fn open(path: &Path) -> io::Result<Region> {
+        let mut region = Region { 
+            file: None, 
+            path: path.to_path_buf(), 
+            entries: vec![Entry::default(); CHUNKS_PER_REGION], 
+            used: vec![true; HEADER_SECTORS as usize], 
+            pending_free: Vec::new() 
+        };
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(region),
+            Err(e) => return Err(e)
+        };
+        let length = file.metadata()?.len();
+
+        // a short or missing header is treated as all chunks absent
+        let mut header = vec![0u8; HEADER_SIZE as usize];
+        if length >= HEADER_SIZE {
+            file.read_exact(&mut header)?;
+        }
+        region.used.resize(length.max(HEADER_SIZE).div_ceil(SECTOR_SIZE) as usize, false);
+
+        let mut dropped = false;
+        for index in 0..CHUNKS_PER_REGION {
+            let bytes = &header[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE];
+            let entry = Entry {
+                offset: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                sectors: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])
+            };
+            if entry.is_empty() {
+                continue;
+            }
+
+            // entries pointing into the header, past the end of the file
+            // or at sectors another chunk already owns are unrecoverable
+            let start = entry.offset as usize;
+            let end = start + entry.sectors as usize;
+            let valid = entry.offset >= HEADER_SECTORS 
+                && end <= region.used.len() 
+                && !region.used[start..end].iter().any(|s| *s);
+            if !valid {
+                log!(Warn, "\u{001b}[31mDropping corrupt chunk entry {} in {}\u{001b}[0m", index, path.display());
+                dropped = true;
+                continue;
+            }
+
+            for sector in region.used[start..end].iter_mut() {
+                *sector = true;
+            }
+            region.entries[index] = entry;
+        }
+
+        region.file = Some(file);
+        // rewrite the table straight away, before anything is allocated
+        // where a dropped entry pointed
+        if dropped {
+            region.write_header()?;
+        }
+        Ok(region)
+    }
+
+    // returns None for chunks that were never saved or whose
+    // record is corrupt, in which case the entry is forgotten
+    // and dropped from the file on its next save
+    pub fn read_chunk(&mut self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        let record = match self.read_record(index)? {
+            Some(record) => record,
+            None => return Ok(None)
+        };
+        match decode_record(&record) {
+            Some(data) => Ok(Some(data)),
+            None => {
+                log!(Warn, "\u{001b}[31mDiscarding corrupt chunk record {} in {}\u{001b}[0m", index, self.path.display());
+                self.pending_free.push(self.entries[index]);
+                self.entries[index] = Entry::default();
+                Ok(None)
+            }
+        }
+    }
+
+    // raw record bytes, trimmed to the length the record claims
+    fn read_record(&mut self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        let entry = self.entries[index];
+        let file = match self.file.as_mut() {
+            Some(file) if !entry.is_empty() => file,
+            _ => return Ok(None)
+        };
+
+        let mut record = vec![0u8; entry.sectors as usize * SECTOR_SIZE as usize];
+        file.seek(SeekFrom::Start(entry.offset as u64 * SECTOR_SIZE))?;
+        // the last sector of the file may be partially written
+        let read = read_up_to(file, &mut record)?;
+        record.truncate(read);
+        if read >= RECORD_HEADER_SIZE {
+            let length = u32::from_le_bytes([record[0], record[1], record[2], record[3]]) as usize;
+            record.truncate(RECORD_HEADER_SIZE + length);
+        }
+        Ok(Some(record))
+    }
+
+    // writes the records to free sectors and syncs them before the offset
+    // table is pointed at them, so a crash mid-save leaves every chunk at
+    // either its old or its new contents; the old sectors are only
+    // reused once the new table is on disk
+    pub fn write_chunks(&mut self, chunks: Vec<(usize, Vec<u8>)>) -> io::Result<()> {
+        let replaced = self.write_records(chunks)?;
+        self.write_header()?;
+        for entry in replaced.into_iter().chain(self.pending_free.drain(..)) {
+            for sector in self.used[entry.offset as usize..(entry.offset + entry.sectors) as usize].iter_mut() {
+                *sector = false;
+            }
+        }
+        Ok(())
+    }
+
+    // first half of a save: the records are on disk but nothing points
+    // at them yet, returns the entries they replace
+    fn write_records(&mut self, chunks: Vec<(usize, Vec<u8>)>) -> io::Result<Vec<Entry>> {
+        if self.file.is_none() {
+            let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&self.path)?;
+            self.file = Some(file);
+        }
+
+        let mut replaced = Vec::new();
+        for (index, data) in chunks {
+            let mut record = encode_record(&data)?;
+            let sectors = (record.len() as u64).div_ceil(SECTOR_SIZE) as u32;
+            let offset = self.allocate(sectors);
+            record.resize(sectors as usize * SECTOR_SIZE as usize, 0);
+
+            let file = self.file.as_mut().unwrap();
+            file.seek(SeekFrom::Start(offset as u64 * SECTOR_SIZE))?;
+            file.write_all(&record)?;
+            replaced.push(self.entries[index]);
+            self.entries[index] = Entry { offset, sectors };
+        }
+        self.file.as_mut().unwrap().sync_data()?;
+        Ok(replaced)
+    }
+
+    // the table is rewritten in place rather than swapped in atomically.
+    // this relies on the disk never tearing an aligned 8 byte write:
+    // entries are 8 byte aligned so none straddles a disk sector, and a
+    // write cut short leaves each one pointing at either the old or the
+    // new record, both of which are still intact on disk
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut header = vec![0u8; HEADER_SIZE as usize];
+        for (index, entry) in self.entries.iter().enumerate() {
+            let bytes = &mut header[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE];
+            bytes[..4].copy_from_slice(&entry.offset.to_le_bytes());
+            bytes[4..].copy_from_slice(&entry.sectors.to_le_bytes());
+        }
+        let file = self.file.as_mut().unwrap();
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        file.sync_data()
+    }
+
+    // first fit, growing the file when no free run is long enough
+    fn allocate(&mut self, sectors: u32) -> u32 {
+        let sectors = sectors as usize;
+        let mut run_start = 0;
+        let mut run_length = 0;
+        for (i, used) in self.used.iter().enumerate() {
+            if *used {
+                run_length = 0;
+                continue;
+            }
+            if run_length == 0 {
+                run_start = i;
+            }
+            run_length += 1;
+            if run_length == sectors {
+                break;
+            }
+        }
+
+        if run_length < sectors {
+            // extend from the trailing free run, if any
+            if run_length == 0 || run_start + run_length != self.used.len() {
+                run_start = self.used.len();
+            }
+            self.used.resize(run_start + sectors, false);
+        }
+
+        for sector in self.used[run_start..run_start + sectors].iter_mut() {
+            *sector = true;
+        }
+        run_start as u32
+    }
+}
+
+fn read_up_to(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match file.read(&mut buffer[read..])? {
+            0 => break,
+            n => read += n
+        }
+    }
+    Ok(read)
+}
+
+fn encode_record(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let payload = encoder.finish()?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.push(COMPRESSION_ZLIB);
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+fn decode_record(record: &[u8]) -> Option<Vec<u8>> {
+    if record.len() < RECORD_HEADER_SIZE {
+        return None
+    }
+
+    let length = u32::from_le_bytes([record[0], record[1], record[2], record[3]]) as usize;
+    let compression = record[4];
+    let crc = u32::from_le_bytes([record[5], record[6], record[7], record[8]]);
+    let payload = record.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + length)?;
+    if crc32fast::hash(payload) != crc {
+        return None
+    }
+
+    match compression {
+        COMPRESSION_NONE => Some(payload.to_vec()),
+        COMPRESSION_ZLIB => {
+            let mut data = Vec::new();
+            ZlibDecoder::new(payload).read_to_end(&mut data).ok()?;
+            Some(data)
+        },
+        _ => None
+    }
+}
+
+// chunk x, chunk z and serialized chunk contents
+pub type SavedChunk = (i32, i32, Vec<u8>);
+
+// lazily opened region files for one world
+pub struct RegionStore {
+    dir: String,
+    // open regions and the tick each was last used
+    regions: HashMap<(i32, i32), (Region, u64)>,
+    tick: u64
+}
+
+impl RegionStore {
+    pub fn new(dir: String) -> RegionStore {
+        RegionStore { dir, regions: HashMap::new(), tick: 0 }
+    }
+
+    pub fn open_regions(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn load(&mut self, chunk_x: i32, chunk_z: i32) -> io::Result<Option<Vec<u8>>> {
+        let index = chunk_index(chunk_x, chunk_z);
+        self.region(chunk_x, chunk_z)?.read_chunk(index)
+    }
+
+    // saves chunks grouped by region, one write per region file;
+    // returns the chunks whose region could not be written
+    pub fn save_all(&mut self, chunks: Vec<SavedChunk>) -> Vec<(i32, i32)> {
+        let mut by_region: HashMap<(i32, i32), Vec<SavedChunk>> = HashMap::new();
+        for (chunk_x, chunk_z, data) in chunks {
+            by_region.entry(region_key(chunk_x, chunk_z)).or_default().push((chunk_x, chunk_z, data));
+        }
+
+        let mut failed = Vec::new();
+        for (_, chunks) in by_region {
+            let (chunk_x, chunk_z, _) = chunks[0];
+            let coords: Vec<(i32, i32)> = chunks.iter().map(|(x, z, _)| (*x, *z)).collect();
+            let updates = chunks.into_iter().map(|(x, z, data)| (chunk_index(x, z), data)).collect();
+            let result = self.region(chunk_x, chunk_z).and_then(|region| region.write_chunks(updates));
+            if let Err(e) = result {
+                log!(Error, "\u{001b}[31mFailed to save region containing chunk {}, {}: {}\u{001b}[0m", chunk_x, chunk_z, e);
+                failed.extend(coords);
+            }
+        }
+        failed
+    }
+
+    fn region(&mut self, chunk_x: i32, chunk_z: i32) -> io::Result<&mut Region> {
+        let key = region_key(chunk_x, chunk_z);
+        self.tick += 1;
+        if !self.regions.contains_key(&key) {
+            if self.regions.len() >= MAX_OPEN_REGIONS {
+                // every write is on disk before write_chunks returns, so closing is enough
+                let oldest = self.regions.iter()
+                    .min_by_key(|(_, (_, used))| *used)
+                    .map(|(key, _)| *key)
+                    .unwrap();
+                self.regions.remove(&oldest);
+            }
+            let path = format!("{}/r.{}.{}.rcr", self.dir, key.0, key.1);
+            self.regions.insert(key, (Region::open(Path::new(&path))?, self.tick));
+        }
+        let (region, used) = self.regions.get_mut(&key).unwrap();
+        *used = self.tick;
+        Ok(region)
+    }
+
+    // moves chunks saved one file per chunk, as in `chunks/<x>_<z>`,
+    // into region files, removing the old files once all are copied
+    pub fn migrate_chunk_dir(&mut self, chunk_dir: &str) -> io::Result<usize> {
+        let mut migrated = Vec::new();
+        let mut chunks = Vec::new();
+        for entry in fs::read_dir(chunk_dir)? {
+            let path = entry?.path();
+            let coords = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split_once('_'))
+                .and_then(|(x, z)| Some((x.parse::<i32>().ok()?, z.parse::<i32>().ok()?)));
+            let (chunk_x, chunk_z) = match coords {
+                Some(coords) => coords,
+                None => continue
+            };
+            chunks.push((chunk_x, chunk_z, fs::read(&path)?));
+            migrated.push(path);
+        }
+
+        if !self.save_all(chunks).is_empty() {
+            return Err(io::Error::other("failed to write region files"))
+        }
+        for path in migrated.iter() {
+            fs::remove_file(path)?;
+        }
+        // only succeeds if nothing unrecognized was left behind
+        let _ = fs::remove_dir(chunk_dir);
+        Ok(migrated.len())
+    }
+}
+
+fn region_key(chunk_x: i32, chunk_z: i32) -> (i32, i32) {
+    (chunk_x.div_euclid(REGION_SIZE), chunk_z.div_euclid(REGION_SIZE))
+}
+
+fn chunk_index(chunk_x: i32, chunk_z: i32) -> usize {
+    let local_x = chunk_x.rem_euclid(REGION_SIZE);
+    let local_z = chunk_z.rem_euclid(REGION_SIZE);
+    (local_x + local_z * REGION_SIZE) as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    // a fresh directory under the system temp dir, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("rustycraft-region-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn region(&self) -> PathBuf {
+            self.0.join("r.0.0.rcr")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // random bytes barely compress, so this fills about that many sectors
+    fn incompressible(sectors: usize, seed: u64) -> Vec<u8> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..sectors * SECTOR_SIZE as usize - 64).map(|_| rng.gen()).collect()
+    }
+
+    fn file_sectors(path: &Path) -> u64 {
+        fs::metadata(path).unwrap().len() / SECTOR_SIZE
+    }
+
+    #[test]
+    fn saves_only_touch_changed_chunks() {
+        let dir = TempDir::new("changed");
+        let mut region = Region::open(&dir.region()).unwrap();
+        region.write_chunks(vec![(0, b"zero".to_vec()), (1, b"one".to_vec())]).unwrap();
+        let untouched = region.entries[1].offset;
+
+        region.write_chunks(vec![(0, b"zero again".to_vec())]).unwrap();
+        assert_eq!(region.entries[1].offset, untouched);
+        assert_eq!(region.read_chunk(0).unwrap().unwrap(), b"zero again");
+        assert_eq!(region.read_chunk(1).unwrap().unwrap(), b"one");
+    }
+
+    #[test]
+    fn freed_sectors_are_reused() {
+        let dir = TempDir::new("reuse");
+        let mut region = Region::open(&dir.region()).unwrap();
+        for seed in 0..10 {
+            region.write_chunks(vec![(5, incompressible(2, seed))]).unwrap();
+        }
+        // the new copy is written before the old one is freed, so two
+        // copies fit in the file whatever the number of saves
+        assert_eq!(file_sectors(&dir.region()), HEADER_SECTORS as u64 + 4);
+
+        let mut reopened = Region::open(&dir.region()).unwrap();
+        assert_eq!(reopened.read_chunk(5).unwrap().unwrap(), incompressible(2, 9));
+    }
+
+    #[test]
+    fn records_are_synced_before_the_table_points_at_them() {
+        let dir = TempDir::new("order");
+        let mut region = Region::open(&dir.region()).unwrap();
+        region.write_chunks(vec![(3, b"old".to_vec())]).unwrap();
+        let old = region.entries[3];
+
+        // simulate a crash after the new record is written but before
+        // the table is: the file on disk still points at the old copy
+        let header = fs::read(dir.region()).unwrap()[..HEADER_SIZE as usize].to_vec();
+        region.write_chunks(vec![(3, b"new".to_vec())]).unwrap();
+        let mut file = OpenOptions::new().write(true).open(dir.region()).unwrap();
+        file.write_all(&header).unwrap();
+        drop(file);
+
+        let mut reopened = Region::open(&dir.region()).unwrap();
+        assert_eq!(reopened.entries[3].offset, old.offset);
+        assert_eq!(reopened.read_chunk(3).unwrap().unwrap(), b"old");
+    }
+
+    #[test]
+    fn crash_before_the_table_is_written_keeps_old_chunks() {
+        let dir = TempDir::new("crash");
+        let mut region = Region::open(&dir.region()).unwrap();
+        region.write_chunks(vec![(3, b"old".to_vec()), (4, b"four".to_vec())]).unwrap();
+
+        // the records are synced, then the server dies before the table is written
+        region.write_records(vec![(3, incompressible(2, 3)), (5, b"five".to_vec())]).unwrap();
+        drop(region);
+
+        let mut reopened = Region::open(&dir.region()).unwrap();
+        assert_eq!(reopened.read_chunk(3).unwrap().unwrap(), b"old");
+        assert_eq!(reopened.read_chunk(4).unwrap().unwrap(), b"four");
+        assert!(reopened.read_chunk(5).unwrap().is_none());
+
+        // the orphaned records are just free space to the next save
+        reopened.write_chunks(vec![(6, incompressible(2, 6))]).unwrap();
+        assert_eq!(reopened.read_chunk(3).unwrap().unwrap(), b"old");
+        assert_eq!(reopened.read_chunk(6).unwrap().unwrap(), incompressible(2, 6));
+    }
+
+    #[test]
+    fn torn_table_leaves_every_chunk_whole() {
+        let dir = TempDir::new("torn");
+        let mut region = Region::open(&dir.region()).unwrap();
+        // entries in the first and the second sector of the table
+        region.write_chunks(vec![(0, b"old zero".to_vec()), (1000, b"old far".to_vec())]).unwrap();
+        let header = fs::read(dir.region()).unwrap()[..HEADER_SIZE as usize].to_vec();
+        region.write_chunks(vec![(0, b"new zero".to_vec()), (1000, b"new far".to_vec())]).unwrap();
+        drop(region);
+
+        // only the first sector of the new table made it to disk
+        let mut file = OpenOptions::new().write(true).open(dir.region()).unwrap();
+        file.seek(SeekFrom::Start(SECTOR_SIZE)).unwrap();
+        file.write_all(&header[SECTOR_SIZE as usize..]).unwrap();
+        drop(file);
+
+        let mut reopened = Region::open(&dir.region()).unwrap();
+        assert_eq!(reopened.read_chunk(0).unwrap().unwrap(), b"new zero");
+        assert_eq!(reopened.read_chunk(1000).unwrap().unwrap(), b"old far");
+    }
+
+    fn set_entry(path: &Path, index: usize, offset: u32, sectors: u32) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start((index * ENTRY_SIZE) as u64)).unwrap();
+        file.write_all(&offset.to_le_bytes()).unwrap();
+        file.write_all(&sectors.to_le_bytes()).unwrap();
+    }
+
+    #[test]
+    fn truncated_header_reads_as_empty() {
+        let dir = TempDir::new("truncated");
+        fs::write(dir.region(), [0xab; 100]).unwrap();
+        let mut region = Region::open(&dir.region()).unwrap();
+        assert!((0..CHUNKS_PER_REGION).all(|index| region.read_chunk(index).unwrap().is_none()));
+
+        region.write_chunks(vec![(7, b"seven".to_vec())]).unwrap();
+        let mut reopened = Region::open(&dir.region()).unwrap();
+        assert_eq!(reopened.read_chunk(7).unwrap().unwrap(), b"seven");
+    }
+
+    #[test]
+    fn bad_entries_are_dropped() {
+        let dir = TempDir::new("entries");
+        let mut region = Region::open(&dir.region()).unwrap();
+        region.write_chunks(vec![(0, b"zero".to_vec()), (1, b"one".to_vec())]).unwrap();
+        let first = region.entries[0];
+        drop(region);
+
+        // overlapping chunk 0, inside the header, past the end of the file
+        set_entry(&dir.region(), 1, first.offset, 1);
+        set_entry(&dir.region(), 2, 0, 1);
+        set_entry(&dir.region(), 3, 1000, 1);
+        set_entry(&dir.region(), 4, u32::MAX, u32::MAX);
+
+        let mut region = Region::open(&dir.region()).unwrap();
+        assert_eq!(region.read_chunk(0).unwrap().unwrap(), b"zero");
+        for index in 1..=4 {
+            assert!(region.read_chunk(index).unwrap().is_none());
+        }
+        // and they are cleared on disk straight away
+        let reopened = Region::open(&dir.region()).unwrap();
+        assert!((1..=4).all(|index| reopened.entries[index].is_empty()));
+    }
+
+    #[test]
+    fn bad_checksum_discards_the_record() {
+        let dir = TempDir::new("checksum");
+        let mut region = Region::open(&dir.region()).unwrap();
+        region.write_chunks(vec![(0, b"zero".to_vec()), (1, b"one".to_vec())]).unwrap();
+        let entry = region.entries[0];
+        drop(region);
+
+        let mut contents = fs::read(dir.region()).unwrap();
+        contents[entry.offset as usize * SECTOR_SIZE as usize + RECORD_HEADER_SIZE] ^= 0xff;
+        fs::write(dir.region(), &contents).unwrap();
+
+        let mut region = Region::open(&dir.region()).unwrap();
+        assert!(region.read_chunk(0).unwrap().is_none());
+        assert_eq!(region.read_chunk(1).unwrap().unwrap(), b"one");
+
+        // the corrupt sectors are not handed out until the
+        // table without the corrupt entry has been written
+        region.write_chunks(vec![(2, b"two".to_vec())]).unwrap();
+        assert_ne!(region.entries[2].offset, entry.offset);
+        let mut reopened = Region::open(&dir.region()).unwrap();
+        assert!(reopened.entries[0].is_empty());
+        assert_eq!(reopened.read_chunk(2).unwrap().unwrap(), b"two");
+    }
+
+    #[test]
+    fn partial_last_sector() {
+        let dir = TempDir::new("partial");
+        let mut region = Region::open(&dir.region()).unwrap();
+        region.write_chunks(vec![(0, b"zero".to_vec()), (1, incompressible(1, 1))]).unwrap();
+        let last = region.entries[1];
+        drop(region);
+        let record_end = last.offset as u64 * SECTOR_SIZE + RECORD_HEADER_SIZE as u64;
+
+        // padding lost, the record itself is whole
+        let file = OpenOptions::new().write(true).open(dir.region()).unwrap();
+        let payload = fs::read(dir.region()).unwrap()[record_end as usize - RECORD_HEADER_SIZE..][..4].to_vec();
+        let length = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]) as u64;
+        file.set_len(record_end + length).unwrap();
+        let mut region = Region::open(&dir.region()).unwrap();
+        assert_eq!(region.read_chunk(1).unwrap().unwrap(), incompressible(1, 1));
+
+        // cut off part way through the record
+        file.set_len(record_end + length / 2).unwrap();
+        let mut region = Region::open(&dir.region()).unwrap();
+        assert!(region.read_chunk(1).unwrap().is_none());
+        assert_eq!(region.read_chunk(0).unwrap().unwrap(), b"zero");
+    }
+
+    #[test]
+    fn migrates_legacy_chunk_files() {
+        let dir = TempDir::new("migrate");
+        let chunks = dir.0.join("chunks");
+        fs::create_dir_all(&chunks).unwrap();
+        fs::write(chunks.join("0_0"), b"origin").unwrap();
+        fs::write(chunks.join("-1_40"), b"far").unwrap();
+        fs::write(chunks.join("notes.txt"), b"not a chunk").unwrap();
+        let regions = dir.0.join("region");
+        fs::create_dir_all(&regions).unwrap();
+
+        let mut store = RegionStore::new(regions.to_str().unwrap().to_string());
+        assert_eq!(store.migrate_chunk_dir(chunks.to_str().unwrap()).unwrap(), 2);
+        assert!(!chunks.join("0_0").exists() && !chunks.join("-1_40").exists());
+        // left alone, and so is the directory holding it
+        assert!(chunks.join("notes.txt").exists());
+
+        let mut reopened = RegionStore::new(regions.to_str().unwrap().to_string());
+        assert_eq!(reopened.load(0, 0).unwrap().unwrap(), b"origin");
+        assert_eq!(reopened.load(-1, 40).unwrap().unwrap(), b"far");
+        assert!(reopened.load(1, 1).unwrap().is_none());
+        assert!(regions.join("r.-1.1.rcr").exists());
+    }
+
+    #[test]
+    fn open_regions_are_capped() {
+        let dir = TempDir::new("capped");
+        let mut store = RegionStore::new(dir.0.to_str().unwrap().to_string());
+        let count = MAX_OPEN_REGIONS as i32 * 2;
+        for i in 0..count {
+            assert!(store.save_all(vec![(i * REGION_SIZE, 0, vec![i as u8])]).is_empty());
+            assert!(store.open_regions() <= MAX_OPEN_REGIONS);
+        }
+        // the most recently used stay open, closed ones are opened again
+        assert!(store.regions.contains_key(&(count - 1, 0)));
+        assert!(!store.regions.contains_key(&(0, 0)));
+        for i in 0..count {
+            assert_eq!(store.load(i * REGION_SIZE, 0).unwrap().unwrap(), vec![i as u8]);
+        }
+        assert_eq!(store.open_regions(), MAX_OPEN_REGIONS);
+    }
+}
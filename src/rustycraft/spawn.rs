@@ -0,0 +1,37 @@
+use std::{io, path::Path};
+use serde::{Deserialize, Serialize};
+
+use super::files::{read_json, write_json};
+
+pub const DEFAULT_SPAWN_SCATTER: u32 = 8;
+
+// where new players appear, somewhere within radius
+// blocks of x, z; kept in spawn.json
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct SpawnPoint {
+    pub x: i32,
+    pub z: i32,
+    pub radius: u32
+}
+
+impl Default for SpawnPoint {
+    fn default() -> Self {
+        SpawnPoint { x: 0, z: 0, radius: DEFAULT_SPAWN_SCATTER }
+    }
+}
+
+impl SpawnPoint {
+    // reads the spawn point, writing the default one if there is none yet
+    pub fn load_or_create(path: &Path) -> io::Result<SpawnPoint> {
+        let exists = path.exists();
+        let spawn: SpawnPoint = read_json(path)?;
+        if !exists {
+            spawn.save(path)?;
+        }
+        Ok(spawn)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        write_json(path, self)
+    }
+}
@@ -1,47 +1,116 @@
-use std::fs;
+use std::{collections::{HashMap, HashSet}, f32::consts::TAU, fs, io, path::Path};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use noise::{OpenSimplex, Seedable};
+use rand::Rng;
 
-use super::{block_type::BlockType, chunk::Chunk, coord_map::CoordMap};
+use super::{block_type::BlockType, chunk::{CHUNK_HEIGHT, Chunk, DEFAULT_TERRAIN_AMPLITUDE}, coord_map::CoordMap, player_data::PlayerStore, region::RegionStore, spawn::SpawnPoint};
+
+// loaded chunks beyond which the least recently used are unloaded
+pub const DEFAULT_MAX_LOADED_CHUNKS: usize = 2048;
+// chunks within this many chunks of spawn are never unloaded
+pub const DEFAULT_SPAWN_RADIUS: i32 = 2;
+
+// random spots tried for a new player before searching outwards
+const SPAWN_ATTEMPTS: usize = 16;
+// how far past the spawn radius to look for dry land
+const SPAWN_SEARCH_DISTANCE: i32 = 64;
+
+pub struct ChunkStats {
+    pub loaded: usize,
+    pub dirty: usize,
+    pub memory_bytes: usize,
+    pub open_regions: usize
+}
 
-#[derive(Clone)]
 pub struct World {
     chunks: CoordMap<Chunk>,
+    // access tick of every loaded chunk, for least recently used unloading
+    last_used: HashMap<(i32, i32), u64>,
+    tick: u64,
+    pub max_loaded_chunks: usize,
+    pub spawn_radius: i32,
+    pub terrain_amplitude: f32,
     simplex: OpenSimplex,
-    player_chunk_x: i32,
-    player_chunk_z: i32,
-    pub save_dir: String
+    regions: RegionStore,
+    // loaded chunks changed or generated since they were last saved
+    dirty: HashSet<(i32, i32)>,
+    pub spawn: SpawnPoint,
+    // dry spot closest to the spawn point, for when no random spot is dry
+    fallback_spawn: (f32, f32, f32),
+    pub players: PlayerStore,
+    pub name: String,
+    pub seed: u32
 }
 
 // handles world block data and rendering
 impl World {
     pub fn new_with_seed(save_dir: &str, seed: u32) -> World {
         // create world directory if it does not exist
-        let dir = format!("worlds/{}/chunks", save_dir);
+        let dir = format!("worlds/{}/region", save_dir);
         fs::create_dir_all(dir.clone()) 
-            .expect(format!("Failed to recursively create {}", dir.clone()).as_str());
+            .unwrap_or_else(|_| panic!("Failed to recursively create {}", dir));
 
         let chunks = CoordMap::new();
         let simplex = OpenSimplex::new().set_seed(seed);
+        let mut regions = RegionStore::new(dir);
+
+        // worlds from before region files kept one file per chunk
+        let legacy_dir = format!("worlds/{}/chunks", save_dir);
+        if Path::new(&legacy_dir).is_dir() {
+            let migrated = regions.migrate_chunk_dir(&legacy_dir)
+                .unwrap_or_else(|e| panic!("Failed to migrate chunks from {}: {}", legacy_dir, e));
+            log!(Info, "\u{001b}[37;1mMigrated {} chunks to region files\u{001b}[0m", migrated);
+        }
         
-        let save_dir = format!("worlds/{}", save_dir);
-        World { chunks, simplex, player_chunk_x: 0, player_chunk_z: 0, save_dir }
+        let spawn_path = format!("worlds/{}/spawn.json", save_dir);
+        let spawn = SpawnPoint::load_or_create(Path::new(&spawn_path))
+            .unwrap_or_else(|e| panic!("Failed to read spawn point from {}: {}", spawn_path, e));
+        let players_path = format!("worlds/{}/players.json", save_dir);
+        let players = PlayerStore::load(players_path.clone().into())
+            .unwrap_or_else(|e| panic!("Failed to read player data from {}: {}", players_path, e));
+
+        let name = save_dir.to_string();
+        let mut world = World { 
+            chunks, 
+            last_used: HashMap::new(), 
+            tick: 0, 
+            max_loaded_chunks: DEFAULT_MAX_LOADED_CHUNKS, 
+            spawn_radius: DEFAULT_SPAWN_RADIUS, 
+            terrain_amplitude: DEFAULT_TERRAIN_AMPLITUDE, 
+            simplex, 
+            regions, 
+            dirty: HashSet::new(), 
+            spawn, 
+            fallback_spawn: (0.0, 0.0, 0.0), 
+            players, 
+            name, 
+            seed 
+        };
+        world.prepare_spawn();
+        world
     }
 
-    pub fn new(save_dir: &str) -> World {
+    // seed is only used if the world does not exist yet
+    pub fn new(save_dir: &str, seed: Option<u32>) -> World {
         let seed_path = format!("worlds/{}/seed", save_dir);
-        let seed = fs::read_to_string(seed_path.clone());
+        let saved_seed = fs::read_to_string(seed_path.clone());
         // read seed from world dir otherwise create
         // one and write to disk
-        let seed = match seed {
-            Ok(seed) => seed.parse::<u32>().unwrap(),
+        let seed = match saved_seed {
+            Ok(saved_seed) => {
+                let saved_seed = saved_seed.parse::<u32>().unwrap();
+                if let Some(seed) = seed.filter(|seed| *seed != saved_seed) {
+                    log!(Warn, "\u{001b}[31mWorld {} already has seed {}, ignoring seed {}\u{001b}[0m", save_dir, saved_seed, seed);
+                }
+                saved_seed
+            },
             Err(_) => {
-                let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u32;
+                let seed = seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u32);
                 fs::create_dir_all(format!("worlds/{}", save_dir))
                     .expect("Failed to create world directory");
                 fs::write(seed_path.clone(), format!("{}", seed))
-                    .expect(format!("Failed to write seed to {}", seed_path).as_str());
+                    .unwrap_or_else(|_| panic!("Failed to write seed to {}", seed_path));
                 seed
             }
         };
@@ -49,20 +118,178 @@ impl World {
     }
 
     pub fn get_or_insert_chunk(&mut self, chunk_x: i32, chunk_z: i32) -> &Chunk {
+        self.tick += 1;
+        self.last_used.insert((chunk_x, chunk_z), self.tick);
         match self.chunks.contains(chunk_x, chunk_z) {
             true => self.chunks.get(chunk_x, chunk_z).unwrap(),
             false => {
-                let c = Chunk::new(chunk_x, chunk_z, self.simplex, format!("{}/chunks", self.save_dir));
+                let c = self.load_or_generate_chunk(chunk_x, chunk_z);
                 self.chunks.insert(chunk_x, chunk_z, c);
                 self.chunks.get(chunk_x, chunk_z).unwrap()
             }
         }
     }
 
-    pub fn get_chunk_mut(&mut self, chunk_x: i32, chunk_z: i32) -> Option<&mut Chunk> {
-        match self.chunks.contains(chunk_x, chunk_z) {
-            true => self.chunks.get_mut(chunk_x, chunk_z),
-            false => None
+    fn load_or_generate_chunk(&mut self, chunk_x: i32, chunk_z: i32) -> Chunk {
+        let saved = match self.regions.load(chunk_x, chunk_z) {
+            Ok(saved) => saved,
+            Err(e) => {
+                log!(Error, "\u{001b}[31mFailed to load chunk {}, {}: {}\u{001b}[0m", chunk_x, chunk_z, e);
+                None
+            }
+        };
+        if let Some(bytes) = saved {
+            match Chunk::decode(&bytes) {
+                Ok(chunk) => return chunk,
+                Err(e) => log!(Warn, "\u{001b}[31mDiscarding chunk {}, {}: {}\u{001b}[0m", chunk_x, chunk_z, e)
+            }
+        }
+
+        self.dirty.insert((chunk_x, chunk_z));
+        Chunk::new(chunk_x, chunk_z, self.simplex, self.terrain_amplitude)
+    }
+
+    // writes every dirty chunk to its region file and returns how many
+    // were saved; chunks whose region failed to save stay dirty
+    pub fn save(&mut self) -> usize {
+        if let Err(e) = self.players.save() {
+            log!(Error, "\u{001b}[31mFailed to save player data: {}\u{001b}[0m", e);
+        }
+
+
+        let mut chunks = Vec::new();
+        for (chunk_x, chunk_z) in self.dirty.drain() {
+            if let Some(chunk) = self.chunks.get(chunk_x, chunk_z) {
+                chunks.push((chunk_x, chunk_z, chunk.encode()));
+            }
+        }
+
+        let attempted = chunks.len();
+        let failed = self.regions.save_all(chunks);
+        let saved = attempted - failed.len();
+        self.dirty.extend(failed);
+        saved
+    }
+
+    // unloads the least recently used chunks outside the spawn area until
+    // at most max_loaded_chunks remain, saving dirty ones first; returns
+    // how many were unloaded
+    pub fn unload_excess_chunks(&mut self) -> usize {
+        let loaded = self.chunks.len();
+        if loaded <= self.max_loaded_chunks {
+            return 0
+        }
+
+        let radius = self.spawn_radius;
+        let (spawn_x, spawn_z, _, _) = self.localize_coords_to_chunk(self.spawn.x, self.spawn.z);
+        let mut candidates: Vec<(u64, i32, i32)> = self.chunks.iter()
+            .filter(|(x, z, _)| (x - spawn_x).abs() > radius || (z - spawn_z).abs() > radius)
+            .map(|(x, z, _)| (self.last_used.get(&(x, z)).copied().unwrap_or(0), x, z))
+            .collect();
+        candidates.sort_unstable();
+        candidates.truncate(loaded - self.max_loaded_chunks);
+
+        let mut to_save = Vec::new();
+        for (_, chunk_x, chunk_z) in candidates.iter() {
+            if self.dirty.remove(&(*chunk_x, *chunk_z)) {
+                let chunk = self.chunks.get(*chunk_x, *chunk_z).unwrap();
+                to_save.push((*chunk_x, *chunk_z, chunk.encode()));
+            }
+        }
+
+        // chunks that failed to save stay loaded and dirty
+        let failed: HashSet<(i32, i32)> = self.regions.save_all(to_save).into_iter().collect();
+        let mut unloaded = 0;
+        for (_, chunk_x, chunk_z) in candidates {
+            if failed.contains(&(chunk_x, chunk_z)) {
+                self.dirty.insert((chunk_x, chunk_z));
+                continue;
+            }
+            self.chunks.remove(chunk_x, chunk_z);
+            self.last_used.remove(&(chunk_x, chunk_z));
+            unloaded += 1;
+        }
+        unloaded
+    }
+
+    // picks a random dry spot within the spawn radius, or failing that the
+    // dry spot closest to the spawn point; spots in chunks that are not
+    // loaded are skipped, so a player joining never waits on generation
+    pub fn find_spawn(&self) -> (f32, f32, f32) {
+        let SpawnPoint { x, z, radius } = self.spawn;
+        let mut rng = rand::thread_rng();
+        for _ in 0..SPAWN_ATTEMPTS {
+            let angle = rng.gen::<f32>() * TAU;
+            let distance = radius as f32 * rng.gen::<f32>().sqrt();
+            let spawn_x = x + (distance * angle.cos()).round() as i32;
+            let spawn_z = z + (distance * angle.sin()).round() as i32;
+            if let Some(y) = self.dry_surface(spawn_x, spawn_z) {
+                return (spawn_x as f32, y as f32, spawn_z as f32)
+            }
+        }
+        self.fallback_spawn
+    }
+
+    // loads the chunks around the spawn point and searches rings around
+    // it for the closest dry spot, generating chunks as needed; done up
+    // front as it can take a hundred chunks over open ocean
+    fn prepare_spawn(&mut self) {
+        let (spawn_x, spawn_z, _, _) = self.localize_coords_to_chunk(self.spawn.x, self.spawn.z);
+        for chunk_x in spawn_x - self.spawn_radius..=spawn_x + self.spawn_radius {
+            for chunk_z in spawn_z - self.spawn_radius..=spawn_z + self.spawn_radius {
+                self.get_or_insert_chunk(chunk_x, chunk_z);
+            }
+        }
+        self.fallback_spawn = self.search_dry_spot();
+    }
+
+    fn search_dry_spot(&mut self) -> (f32, f32, f32) {
+        let SpawnPoint { x, z, radius } = self.spawn;
+        for ring in 0..=(radius as i32 + SPAWN_SEARCH_DISTANCE) {
+            for dx in -ring..=ring {
+                for dz in -ring..=ring {
+                    if dx.abs() != ring && dz.abs() != ring {
+                        continue;
+                    }
+                    let (chunk_x, chunk_z, _, _) = self.localize_coords_to_chunk(x + dx, z + dz);
+                    self.get_or_insert_chunk(chunk_x, chunk_z);
+                    if let Some(y) = self.dry_surface(x + dx, z + dz) {
+                        return ((x + dx) as f32, y as f32, (z + dz) as f32)
+                    }
+                }
+            }
+        }
+
+        // nothing but ocean
+        let y = self.highest_in_column(x, z).unwrap_or(0);
+        (x as f32, y as f32, z as f32)
+    }
+
+    pub fn set_spawn(&mut self, spawn: SpawnPoint) -> io::Result<()> {
+        spawn.save(Path::new(&format!("worlds/{}/spawn.json", self.name)))?;
+        self.spawn = spawn;
+        self.prepare_spawn();
+        Ok(())
+    }
+
+    // height of the top block of a column, unless it is water
+    // or the column's chunk is not loaded
+    fn dry_surface(&self, world_x: i32, world_z: i32) -> Option<usize> {
+        let (chunk_x, chunk_z, local_x, local_z) = self.localize_coords_to_chunk(world_x, world_z);
+        let chunk = self.get_chunk(chunk_x, chunk_z)?;
+        let y = chunk.highest_in_column(local_x, local_z);
+        match chunk.blocks.get(local_x, y, local_z) {
+            BlockType::Water => None,
+            _ => Some(y)
+        }
+    }
+
+    pub fn chunk_stats(&self) -> ChunkStats {
+        ChunkStats {
+            loaded: self.chunks.len(),
+            dirty: self.dirty.len(),
+            memory_bytes: self.chunks.iter().map(|(_, _, chunk)| chunk.memory_usage()).sum(),
+            open_regions: self.regions.open_regions()
         }
     }
 
@@ -72,19 +299,31 @@ impl World {
 
     pub fn highest_in_column(&self, world_x: i32, world_z: i32) -> Option<usize> {
         let (chunk_x, chunk_z, local_x, local_z) = self.localize_coords_to_chunk(world_x, world_z);
-        let chunk = self.get_chunk(chunk_x, chunk_z);
-        if chunk.is_none() {
-            return None
-        }
+        let chunk = self.get_chunk(chunk_x, chunk_z)?;
+        Some(chunk.highest_in_column(local_x, local_z))
+    }
 
-        Some(chunk.unwrap().highest_in_column(local_x, local_z))
+    // block at world coordinates, None if its chunk is not loaded
+    pub fn get_block(&self, world_x: i32, world_y: i32, world_z: i32) -> Option<BlockType> {
+        let (chunk_x, chunk_z, local_x, local_z) = self.localize_coords_to_chunk(world_x, world_z);
+        let chunk = self.get_chunk(chunk_x, chunk_z)?;
+        if world_y < 0 || world_y >= CHUNK_HEIGHT as i32 {
+            return Some(BlockType::Air)
+        }
+        Some(chunk.blocks.get(local_x, world_y as usize, local_z))
     }
 
-    pub fn set_block(&mut self, world_x: i32, world_y: i32, world_z: i32, block: BlockType) {
+    // returns false if world_y is outside the world
+    pub fn set_block(&mut self, world_x: i32, world_y: i32, world_z: i32, block: BlockType) -> bool {
+        if world_y < 0 || world_y >= CHUNK_HEIGHT as i32 {
+            return false
+        }
         let (chunk_x, chunk_z, local_x, local_z) = self.localize_coords_to_chunk(world_x, world_z);
         self.get_or_insert_chunk(chunk_x, chunk_z);
-        let chunk = self.get_chunk_mut(chunk_x, chunk_z).unwrap();
+        let chunk = self.chunks.get_mut(chunk_x, chunk_z).unwrap();
         chunk.set_block(local_x, world_y as usize, local_z, block);
+        self.dirty.insert((chunk_x, chunk_z));
+        true
     }
 
     pub fn localize_coords_to_chunk(&self, world_x: i32, world_z: i32) -> (i32, i32, usize, usize) {
@@ -98,8 +337,8 @@ impl World {
             chunk_z -= 1;
         }
 
-        let local_x = ((chunk_x.abs() * 16 + world_x) % 16).abs() as usize;
-        let local_z = ((chunk_z.abs() * 16 + world_z) % 16).abs() as usize;
+        let local_x = ((chunk_x.abs() * 16 + world_x) % 16).unsigned_abs() as usize;
+        let local_z = ((chunk_z.abs() * 16 + world_z) % 16).unsigned_abs() as usize;
         (chunk_x, chunk_z, local_x, local_z)
     }
 }
\ No newline at end of file